@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::link::Url;
+
+/// Rewrite of the urls of an alias host onto the canonical one
+///
+/// ```toml
+/// [[canonical.hosts]]
+/// host = "www.example.com"
+/// canonical_host = "example.com"
+/// canonical_scheme = "https"
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostAlias {
+    pub host: String,
+    pub canonical_host: Option<String>,
+    pub canonical_scheme: Option<String>,
+}
+
+/// How the urls are canonicalized before being hashed
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CanonicalConfig {
+    /// Parameters removed from the query and from the path (`;jsessionid=`),
+    /// case insensitive, a `*` at the end matches any suffix
+    pub strip_params: Vec<String>,
+    pub sort_query: bool,
+    pub drop_fragment: bool,
+    pub hosts: Vec<HostAlias>,
+}
+
+impl Default for CanonicalConfig {
+    fn default() -> Self {
+        CanonicalConfig {
+            strip_params: ["utm_*", "jsessionid", "phpsessid", "sesskey", "fbclid", "gclid"]
+                .iter()
+                .map(|param| param.to_string())
+                .collect(),
+            sort_query: true,
+            drop_fragment: true,
+            hosts: Vec::new(),
+        }
+    }
+}
+
+/// Canonicalize the urls so that the urls of the same page have the same hash
+#[derive(Default)]
+pub struct Canonicalizer {
+    strip_params: Vec<String>,
+    sort_query: bool,
+    drop_fragment: bool,
+    hosts: HashMap<String, HostAlias>,
+}
+
+impl Canonicalizer {
+    pub fn new(config: &CanonicalConfig) -> Self {
+        Canonicalizer {
+            strip_params: config.strip_params.iter().map(|param| param.to_ascii_lowercase()).collect(),
+            sort_query: config.sort_query,
+            drop_fragment: config.drop_fragment,
+            hosts: config
+                .hosts
+                .iter()
+                .map(|alias| (alias.host.to_ascii_lowercase(), alias.clone()))
+                .collect(),
+        }
+    }
+
+    fn is_stripped(&self, param: &str) -> bool {
+        let name = param.split('=').next().unwrap_or_default().to_ascii_lowercase();
+        self.strip_params.iter().any(|stripped| match stripped.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == *stripped,
+        })
+    }
+
+    pub fn canonicalize(&self, url: &Url) -> Url {
+        let mut url = url.clone();
+        if let Some(alias) = self.hosts.get(url.get_host()) {
+            if let Some(scheme) = &alias.canonical_scheme {
+                url.set_scheme(scheme);
+            }
+            if let Some(host) = &alias.canonical_host {
+                url.set_host(host);
+            }
+        }
+
+        // Path parameters such as `/page;jsessionid=0A1B`, `;` is escaped by the parsing
+        if url.get_path().contains("%3B") {
+            let path = url
+                .get_path()
+                .split('/')
+                .map(|segment| {
+                    let mut parts = segment.split("%3B");
+                    let mut segment = parts.next().unwrap_or_default().to_string();
+                    for part in parts.filter(|part| !self.is_stripped(part)) {
+                        segment.push_str("%3B");
+                        segment.push_str(part);
+                    }
+                    segment
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            url.set_path(path);
+        }
+
+        if let Some(query) = url.get_query() {
+            let mut params: Vec<&str> = query
+                .split('&')
+                .filter(|param| !param.is_empty() && !self.is_stripped(param))
+                .collect();
+            if self.sort_query {
+                params.sort_by_key(|param| param.split('=').next().unwrap_or_default());
+            }
+            let query = (!params.is_empty()).then(|| params.join("&"));
+            url.set_query(query);
+        }
+
+        if self.drop_fragment {
+            url.set_fragment(None);
+        }
+        url
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_canonicalize() {
+        let canonicalizer = Canonicalizer::default();
+        let canonicalize = |url: &str| canonicalizer.canonicalize(&Url::parse(url).unwrap()).to_string();
+        assert_eq!(canonicalize("https://a.fr/p?b=1&a=2#top"), "https://a.fr/p?b=1&a=2#top");
+
+        let canonicalizer = Canonicalizer::new(&CanonicalConfig::default());
+        let canonicalize = |url: &str| canonicalizer.canonicalize(&Url::parse(url).unwrap()).to_string();
+        assert_eq!(canonicalize("https://a.fr/p?b=1&a=2#top"), "https://a.fr/p?a=2&b=1");
+        assert_eq!(canonicalize("https://a.fr/p?b=1&a=3&a=2"), "https://a.fr/p?a=3&a=2&b=1");
+        assert_eq!(
+            canonicalize("https://a.fr/p?utm_source=x&id=1&UTM_MEDIUM=y&fbclid=z"),
+            "https://a.fr/p?id=1"
+        );
+        assert_eq!(canonicalize("https://a.fr/p?utm_source=x"), "https://a.fr/p");
+        assert_eq!(canonicalize("https://moodle.a.fr/mod/view.php?sesskey=abc&id=4"), "https://moodle.a.fr/mod/view.php?id=4");
+        assert_eq!(canonicalize("https://a.fr/app/page;jsessionid=0A1B?x=1"), "https://a.fr/app/page?x=1");
+        assert_eq!(canonicalize("https://a.fr/app/page;v=2"), "https://a.fr/app/page%3Bv=2");
+
+        let config = Config::parse(
+            r#"
+            [canonical]
+            strip_params = ["ref"]
+            sort_query = false
+
+            [[canonical.hosts]]
+            host = "www.example.com"
+            canonical_host = "example.com"
+            canonical_scheme = "https"
+
+            [[canonical.hosts]]
+            host = "example.com"
+            canonical_scheme = "https"
+            "#,
+        )
+        .unwrap();
+        let canonicalizer = Canonicalizer::new(&config.canonical);
+        let canonicalize = |url: &str| canonicalizer.canonicalize(&Url::parse(url).unwrap()).to_string();
+        assert_eq!(canonicalize("http://www.example.com:443/a?b=1&ref=x&a=2"), "https://example.com/a?b=1&a=2");
+        assert_eq!(canonicalize("http://example.com/a"), "https://example.com/a");
+        assert_eq!(canonicalize("http://example.com:8080/a"), "https://example.com:8080/a");
+        assert_eq!(canonicalize("http://other.com/a?utm_source=x"), "http://other.com/a?utm_source=x");
+    }
+}
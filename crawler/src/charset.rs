@@ -0,0 +1,111 @@
+use std::borrow::Cow;
+
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+/// Number of bytes searched for a `<meta charset>` or a xml declaration
+const PRESCAN_LENGTH: usize = 4 * 1024;
+/// Number of bytes given to the statistical detection
+const DETECTION_LENGTH: usize = 64 * 1024;
+
+/// Get the encoding of the `charset` parameter of a `Content-Type` header
+fn from_header(mime: &str) -> Option<&'static Encoding> {
+    mime.split(';').skip(1).find_map(|parameter| {
+        let (name, value) = parameter.split_once('=')?;
+        if !name.trim().eq_ignore_ascii_case("charset") {
+            return None;
+        }
+        Encoding::for_label(value.trim().trim_matches(['"', '\'']).as_bytes())
+    })
+}
+
+/// Read the value following `name=` in a tag, quoted or not
+fn attribute_value<'a>(tag: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    let start = tag.windows(name.len()).position(|window| window == name)? + name.len();
+    let rest = tag[start..].trim_ascii_start().strip_prefix(b"=")?.trim_ascii_start();
+    let rest = rest.strip_prefix(b"\"").or_else(|| rest.strip_prefix(b"'")).unwrap_or(rest);
+    let end = rest
+        .iter()
+        .position(|c| !(c.is_ascii_alphanumeric() || b"-_.:".contains(c)))
+        .unwrap_or(rest.len());
+    Some(&rest[..end])
+}
+
+/// Find the encoding declared at the start of the content: the `<meta charset>`,
+/// the `<meta http-equiv="Content-Type">` or the xml declaration
+fn prescan(bytes: &[u8]) -> Option<&'static Encoding> {
+    let start = bytes[..bytes.len().min(PRESCAN_LENGTH)].to_ascii_lowercase();
+    let mut tags = start.split(|c| *c == b'<');
+    let label = tags.find_map(|tag| {
+        if tag.starts_with(b"meta") {
+            attribute_value(tag, b"charset")
+        } else if tag.starts_with(b"?xml") {
+            attribute_value(tag, b"encoding")
+        } else {
+            None
+        }
+    })?;
+    // A document read as ASCII cannot be in UTF-16, it is UTF-8
+    Encoding::for_label(label).map(|encoding| {
+        if encoding == UTF_16LE || encoding == UTF_16BE {
+            UTF_8
+        } else {
+            encoding
+        }
+    })
+}
+
+/// Find the encoding of a content, in order of precedence: the byte order mark, the
+/// `charset` of the `Content-Type` header, the charset declared in the content and
+/// the statistical detection
+pub fn detect(bytes: &[u8], mime: Option<&str>) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    if let Some(encoding) = mime.and_then(from_header).or_else(|| prescan(bytes)) {
+        return encoding;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(&bytes[..bytes.len().min(DETECTION_LENGTH)], bytes.len() <= DETECTION_LENGTH);
+    detector.guess(None, true)
+}
+
+/// Decode a content to UTF-8 with its encoding, the invalid sequences are replaced
+pub fn decode<'a>(bytes: &'a [u8], encoding: &'static Encoding) -> Cow<'a, str> {
+    encoding.decode(bytes).0
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+
+    use super::*;
+
+    #[test]
+    fn test_charset() {
+        let latin = b"<html><head><title>Universit\xe9</title></head><body>\xc9t\xe9 \xe0 Rouen, \xe7a d\xe9marre</body></html>";
+        // ISO-8859-1 is decoded as windows-1252, as the browsers do
+        assert_eq!(detect(latin, Some("text/html; charset=ISO-8859-1")), WINDOWS_1252);
+        assert_eq!(detect(latin, Some("text/html")), WINDOWS_1252);
+        assert_eq!(
+            decode(latin, detect(latin, None)),
+            "<html><head><title>Université</title></head><body>Été à Rouen, ça démarre</body></html>"
+        );
+
+        let meta = b"<!DOCTYPE html><html><head><meta charset=\"windows-1252\"></head>\x80";
+        assert_eq!(detect(meta, None), WINDOWS_1252);
+        let http_equiv = b"<html><head><META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=Shift_JIS\">";
+        assert_eq!(detect(http_equiv, None), SHIFT_JIS);
+        let xml = b"<?xml version=\"1.0\" encoding='ISO-8859-1'?><rss></rss>";
+        assert_eq!(detect(xml, Some("application/rss+xml")), WINDOWS_1252);
+
+        // The byte order mark wins over the header, and the header over the meta
+        assert_eq!(detect(b"\xef\xbb\xbf<meta charset=latin1>", Some("text/html; charset=latin1")), UTF_8);
+        assert_eq!(detect(meta, Some("text/html; charset=\"utf-8\"")), UTF_8);
+        assert_eq!(detect(b"<meta charset=utf-16>", None), UTF_8);
+        assert_eq!(detect("déjà vu".as_bytes(), Some("text/plain; charset=unknown")), UTF_8);
+    }
+}
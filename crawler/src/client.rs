@@ -0,0 +1,285 @@
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc, time::Duration};
+
+use futures::lock::{Mutex, MutexGuard};
+use reqwest::{
+    cookie::Jar,
+    header::{HeaderMap, HeaderName, HeaderValue},
+    redirect, Certificate, Client, ClientBuilder, Method, NoProxy, Proxy, RequestBuilder,
+};
+use serde::Deserialize;
+
+use crate::{config::ConfigError, link::Url, robots::ROBOTS_NAME};
+
+/// Headers and certificates of a host
+///
+/// ```toml
+/// [[client.hosts]]
+/// host = "intranet.example.com"
+/// accept_invalid_certs = true
+/// headers = { "Accept-Language" = "en" }
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostProfile {
+    pub host: String,
+    /// Headers added to the default ones, or replacing them
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Accept invalid and self-signed certificates, for internal hosts
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+}
+
+/// Profile of the client used for all the requests
+///
+/// ```toml
+/// [client]
+/// user_agent = "open-finder/0.1"
+/// timeout = 60
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    pub user_agent: String,
+    /// Headers sent with every request
+    pub headers: BTreeMap<String, String>,
+    pub hosts: Vec<HostProfile>,
+    /// Timeouts in seconds: to connect, between two reads, and for the whole request
+    pub connect_timeout: f64,
+    pub read_timeout: f64,
+    pub timeout: f64,
+    /// Proxy of all the requests, like `http://proxy.example.com:3128`
+    pub proxy: Option<String>,
+    /// Hosts reached without the proxy, comma separated like `NO_PROXY`
+    pub no_proxy: Option<String>,
+    pub http1_only: bool,
+    /// Maximum number of redirects followed, 0 to not follow them. The redirects
+    /// from a host with headers or invalid certificates stop at a change of host.
+    pub max_redirects: usize,
+    /// PEM files of root certificates trusted in addition to the system ones
+    pub root_certificates: Vec<PathBuf>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            user_agent: format!("{}/{}", ROBOTS_NAME, env!("CARGO_PKG_VERSION")),
+            headers: BTreeMap::from([
+                (
+                    String::from("Accept"),
+                    String::from("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+                ),
+                (String::from("Accept-Language"), String::from("fr-FR,fr;q=0.9,en;q=0.8")),
+            ]),
+            hosts: Vec::new(),
+            connect_timeout: 2.0,
+            read_timeout: 10.0,
+            timeout: 60.0,
+            proxy: None,
+            no_proxy: None,
+            http1_only: false,
+            max_redirects: 10,
+            root_certificates: Vec::new(),
+        }
+    }
+}
+
+fn to_header_map(headers: &BTreeMap<String, String>) -> Result<HeaderMap, ConfigError> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| ConfigError::InvalidHeader(name.clone()))?;
+        let value = HeaderValue::from_str(value).map_err(|_| ConfigError::InvalidHeader(name.to_string()))?;
+        map.insert(name, value);
+    }
+    Ok(map)
+}
+
+/// Follow at most `max_redirects` redirects, but stop at a change of host when
+/// the redirects start from one of the `guarded` hosts, so that their headers
+/// and their invalid certificates are not used for another host
+fn redirect_policy(max_redirects: usize, guarded: Arc<Vec<String>>) -> redirect::Policy {
+    if max_redirects == 0 {
+        return redirect::Policy::none();
+    }
+    redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() > max_redirects {
+            return attempt.error("too many redirects");
+        }
+        let host = |url: &reqwest::Url| url.host_str().map(str::to_ascii_lowercase);
+        let from = attempt.previous().first().and_then(host);
+        let is_guarded = from.as_ref().is_some_and(|from| guarded.contains(from));
+        if is_guarded && host(attempt.url()) != from {
+            attempt.stop()
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+/// The client of the profile, with the headers of the hosts. The clones share
+/// the connection pool, the cookies and the login lock, and send their requests
+/// concurrently.
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client,
+    /// Accepts the invalid certificates, for the hosts that allow them
+    insecure: Option<Client>,
+    hosts: Arc<Vec<(String, HeaderMap, bool)>>,
+    /// Held during a login, so that the pages redirected to the login page
+    /// do not all log in
+    login: Arc<Mutex<()>>,
+}
+
+impl HttpClient {
+    pub fn new(config: &ClientConfig) -> Result<Self, ConfigError> {
+        let hosts = config
+            .hosts
+            .iter()
+            .map(|host| Ok((host.host.to_ascii_lowercase(), to_header_map(&host.headers)?, host.accept_invalid_certs)))
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+        let guarded = hosts
+            .iter()
+            .filter(|(_, headers, accept_invalid_certs)| !headers.is_empty() || *accept_invalid_certs)
+            .map(|(host, _, _)| host.clone())
+            .collect();
+        let guarded = Arc::new(guarded);
+
+        let jar = Arc::new(Jar::default());
+        let builder = || -> Result<ClientBuilder, ConfigError> {
+            let mut builder = Client::builder()
+                .cookie_provider(Arc::clone(&jar))
+                .user_agent(&config.user_agent)
+                .default_headers(to_header_map(&config.headers)?)
+                .connect_timeout(Duration::from_secs_f64(config.connect_timeout))
+                .read_timeout(Duration::from_secs_f64(config.read_timeout))
+                .timeout(Duration::from_secs_f64(config.timeout))
+                .redirect(redirect_policy(config.max_redirects, Arc::clone(&guarded)));
+            if config.http1_only {
+                builder = builder.http1_only();
+            }
+            if let Some(proxy) = &config.proxy {
+                let no_proxy = config.no_proxy.as_deref().and_then(NoProxy::from_string);
+                builder = builder.proxy(Proxy::all(proxy).map_err(ConfigError::Client)?.no_proxy(no_proxy));
+            }
+            for path in &config.root_certificates {
+                let pem = std::fs::read(path).map_err(|err| ConfigError::Certificate(path.clone(), err))?;
+                builder = builder.add_root_certificate(Certificate::from_pem(&pem).map_err(ConfigError::Client)?);
+            }
+            Ok(builder)
+        };
+
+        let insecure = match hosts.iter().any(|(_, _, accept_invalid_certs)| *accept_invalid_certs) {
+            true => Some(builder()?.danger_accept_invalid_certs(true).build().map_err(ConfigError::Client)?),
+            false => None,
+        };
+        Ok(HttpClient {
+            client: builder()?.build().map_err(ConfigError::Client)?,
+            insecure,
+            hosts: Arc::new(hosts),
+            login: Arc::default(),
+        })
+    }
+
+    /// Start a request with the client and the headers of the host of the url
+    pub fn request(&self, method: Method, url: &Url) -> RequestBuilder {
+        let host = self.hosts.iter().find(|(host, _, _)| *host == url.get_host());
+        let client = match (host, &self.insecure) {
+            (Some((_, _, true)), Some(insecure)) => insecure,
+            _ => &self.client,
+        };
+        let request = client.request(method, url.to_string());
+        match host {
+            Some((_, headers, _)) => request.headers(headers.clone()),
+            None => request,
+        }
+    }
+
+    pub fn get(&self, url: &Url) -> RequestBuilder {
+        self.request(Method::GET, url)
+    }
+
+    pub fn head(&self, url: &Url) -> RequestBuilder {
+        self.request(Method::HEAD, url)
+    }
+
+    pub fn post(&self, url: &Url) -> RequestBuilder {
+        self.request(Method::POST, url)
+    }
+
+    /// Wait for the other logins and prevent new ones until the guard is dropped
+    pub async fn lock_login(&self) -> MutexGuard<'_, ()> {
+        self.login.lock().await
+    }
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        HttpClient::new(&ClientConfig::default()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, test_server};
+
+    #[test]
+    fn test_client_profile() {
+        let config = Config::parse(
+            r#"
+            [client]
+            user_agent = "test-bot/1.0"
+            headers = { "Accept-Language" = "en" }
+            max_redirects = 0
+
+            [[client.hosts]]
+            host = "Intranet.example.com"
+            accept_invalid_certs = true
+            headers = { "X-Token" = "secret" }
+            "#,
+        )
+        .unwrap();
+        let client = HttpClient::new(&config.client).unwrap();
+        assert!(client.insecure.is_some());
+
+        let request = client.get(&Url::parse("https://intranet.example.com/a").unwrap()).build().unwrap();
+        assert_eq!(request.headers()["X-Token"], "secret");
+        let request = client.get(&Url::parse("https://www.example.com/a").unwrap()).build().unwrap();
+        assert!(request.headers().get("X-Token").is_none());
+
+        let invalid = Config::parse("[client]\nheaders = { \"Bad Header\" = \"a\" }").unwrap();
+        assert!(matches!(HttpClient::new(&invalid.client), Err(ConfigError::InvalidHeader(_))));
+        assert!(HttpClient::new(&ClientConfig::default()).unwrap().insecure.is_none());
+
+        let missing = Config::parse("[client]\nroot_certificates = [\"missing.pem\"]").unwrap();
+        let err = HttpClient::new(&missing.client).err().unwrap();
+        assert!(matches!(&err, ConfigError::Certificate(path, _) if path.ends_with("missing.pem")));
+        assert!(err.to_string().contains("missing.pem"));
+    }
+
+    #[tokio::test]
+    async fn test_redirect_to_another_host() {
+        // `/a` redirects to `localhost`, another host on the same port
+        let addr = test_server::serve_with(|path, addr| match path {
+            "/a" => format!("HTTP/1.1 302 Found\r\nLocation: http://localhost:{}/b\r\nContent-Length: 0\r\n\r\n", addr.port()),
+            _ => String::from("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"),
+        }).await;
+        let url = Url::parse(format!("http://{}/a", addr)).unwrap();
+
+        let res = HttpClient::default().get(&url).send().await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        // The token of the host is not sent to the other host
+        let config = Config::parse(
+            r#"
+            [[client.hosts]]
+            host = "127.0.0.1"
+            headers = { "X-Token" = "secret" }
+            "#,
+        )
+        .unwrap();
+        let res = HttpClient::new(&config.client).unwrap().get(&url).send().await.unwrap();
+        assert_eq!(res.status(), 302);
+    }
+}
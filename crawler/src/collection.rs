@@ -1,29 +1,101 @@
-use futures::{self, lock::Mutex};
 use progress_bar::*;
-use reqwest::{Client, ClientBuilder};
+use reqwest::{
+    header::{LOCATION, ORIGIN, REFERER},
+    Response,
+};
 use rpassword::read_password;
 use std::{
-    collections::{HashSet, VecDeque}, fmt::Debug, fs::{self, File, OpenOptions}, io::Write, rc::Rc, sync::{atomic::{AtomicU32, Ordering}, Arc}, time::Duration
+    cmp::Reverse, collections::{BinaryHeap, HashMap, HashSet, VecDeque}, fmt::Debug, fs::{self, File, OpenOptions}, io::Write, sync::{atomic::{AtomicU32, Ordering}, Arc}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}
 };
-use urlencoding;
+use tokio::sync::{AcquireError, OwnedSemaphorePermit};
 
 // TODO: blacklist personal pages
 use crate::{
-    content::Content,
-    link::{HackTraitVecUrlString, Url},
+    canonical::{CanonicalConfig, Canonicalizer},
+    client::HttpClient,
+    config::{Config, ConfigError},
+    content::{Content, ContentType, IndexUpdate},
+    download::{self, DownloadConfig, Head, Truncation},
+    pipeline::{Discovery, FetchJob, Fetched, Pipeline, PipelineConfig},
+    feed::{FeedEntry, FeedItem},
+    html::Alternate,
+    robots::{Directives, RobotsCache, RobotsPolicy, RobotsTxt},
+    scope::{Decision, Scope},
+    sitemap::{self, SitemapEntry, SitemapHint},
+    trap::{Trap, TrapDetector, TrappedPattern},
+    link::{HackTraitVecUrlString, Link, LinkSource, Rel, Url},
     protocols::UriScheme,
 };
-use errors::PageError::{self, *};
+pub use errors::PageError;
+use errors::PageError::*;
 pub struct Page {
     url: Url,
     referers: HashSet<Url>,
-    links: HashSet<Url>,
-    content: Option<Content>,
-    client: Arc<Mutex<Client>>,
+    links: Vec<Link>,
+    /// Texts of the links pointing to the page
+    anchors: Vec<String>,
+    /// What a feed says about the page
+    feed_entry: Option<FeedEntry>,
+    content: Option<Arc<Content>>,
+    client: HttpClient,
     status: u16,
+    robots_policy: RobotsPolicy,
+    /// Directives of the page once the ignored ones are removed
+    robots: Directives,
+    canonicalizer: Arc<Canonicalizer>,
+    /// The canonical url declared by the page when it is another page of the same host,
+    /// the page is then not published
+    canonical: Option<Url>,
+    /// The canonical is ignored, it is not indexable
+    keep_variant: bool,
+    alternates: Vec<Alternate>,
+    /// The items of the page when it is a feed
+    feed_items: Vec<FeedItem>,
+    download: Arc<DownloadConfig>,
+    /// The `Content-Type` header of the page
+    mime: Option<String>,
+    /// The announced length of the body
+    length: Option<u64>,
+    /// Set when the body is over its size limit
+    truncation: Option<Truncation>,
+    /// The redirect to another host that the client did not follow, it is
+    /// fetched as a link
+    redirect: Option<Url>,
+}
+
+/// Maximum number of anchor texts kept for an url
+const MAX_ANCHORS: usize = 32;
+/// Maximum number of variants recorded for a canonical url
+const MAX_VARIANTS: usize = 32;
+/// When a page listed by a sitemap was fetched and when it is due for a new
+/// fetch, in seconds since the Unix epoch
+struct Recrawl {
+    url: Url,
+    fetched: u64,
+    /// Given by the `<changefreq>` of the page
+    due: Option<u64>,
+}
+
+impl Recrawl {
+    /// The page changed since it was fetched according to the `<lastmod>` of the
+    /// hint, or it is due without `<lastmod>`
+    fn is_stale(&self, hint: &SitemapHint, now: u64) -> bool {
+        match hint.lastmod_timestamp() {
+            Some(lastmod) => lastmod > self.fetched,
+            None => self.due.is_some_and(|due| due <= now),
+        }
+    }
+}
+
+/// Seconds since the Unix epoch
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
 }
 
-const CONCURRENT_REQUESTS: usize = 20;
+/// Maximum `Crawl-delay` followed, the longer ones would stop the crawl of a host
+const MAX_CRAWL_DELAY: Duration = Duration::from_secs(60);
+/// Number of pages between two reports of the pages per second
+const THROUGHPUT_WINDOW: usize = 100;
 
 impl Debug for Page {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -32,34 +104,112 @@ impl Debug for Page {
             " - url: {} \n - referers: {:?} \nlinks:\n{}",
             self.url,
             self.referers,
-            self.links.to_string()
+            self.links.iter().collect::<HashSet<_>>().to_string()
         )
     }
 }
 
+/// What the pages of a crawl share to be fetched and parsed: the robots
+/// directives to ignore, how the urls are canonicalized, the size limits of the
+/// bodies and the client
+#[derive(Clone, Default)]
+pub struct FetchOptions {
+    pub robots_policy: RobotsPolicy,
+    pub canonicalizer: Arc<Canonicalizer>,
+    pub download: Arc<DownloadConfig>,
+    pub client: HttpClient,
+}
+
 impl Page {
-    pub async fn new(url: Url, client: Arc<Mutex<Client>>) -> Result<Self, PageError> {
+    /// Fetch and parse a page
+    pub async fn new(url: Url, client: HttpClient) -> Result<Self, PageError> {
+        let options = FetchOptions {
+            client,
+            ..FetchOptions::default()
+        };
+        let mut page = Page::download(FetchJob::new(url), options).await?;
+        page.parse();
+        Ok(page)
+    }
+
+    /// Download the page of the job knowing the texts of the links pointing to it
+    /// and its entry in a feed, it is parsed afterwards
+    pub async fn download(job: FetchJob, options: FetchOptions) -> Result<Self, PageError> {
         let mut page = Page {
-            url: url.clone(),
+            url: job.url,
             referers: HashSet::new(),
-            links: HashSet::new(),
+            links: Vec::new(),
+            anchors: job.anchors,
+            feed_entry: job.feed_entry,
             content: None,
-            client,
+            client: options.client,
             status: 0,
+            robots_policy: options.robots_policy,
+            robots: Directives::default(),
+            canonicalizer: options.canonicalizer,
+            canonical: None,
+            keep_variant: job.keep_variant,
+            alternates: Vec::new(),
+            feed_items: Vec::new(),
+            download: options.download,
+            mime: None,
+            length: None,
+            truncation: None,
+            redirect: None,
         };
         page.fetch().await?;
         Ok(page)
     }
 
+    /// Record the headers of the body, `false` if its announced length is over
+    /// its limit and it must not be downloaded
+    fn accept_head(&mut self, head: Head) -> bool {
+        let kind = ContentType::detect(head.mime.as_deref(), &self.url.get_file_name(), &[]);
+        let is_oversized = head
+            .length
+            .is_some_and(|length| length > self.download.max_size_of(&kind));
+        self.mime = head.mime;
+        self.length = head.length;
+        if is_oversized {
+            self.status = head.status;
+            self.truncation = Some(Truncation::Skipped);
+        }
+        !is_oversized
+    }
+
+    /// Download the body of the response within its size limit
+    async fn read_content(&mut self, res: Response) -> Result<(), PageError> {
+        if !self.accept_head(Head::of(&res)) {
+            return Ok(());
+        }
+        // The limit depends on the type, sniffed from the first bytes
+        let download = Arc::clone(&self.download);
+        let (mime, name) = (self.mime.clone(), self.url.get_file_name());
+        let limit = |start: &[u8]| download.max_size_of(&ContentType::detect(mime.as_deref(), &name, start));
+        let (bytes, truncated) = download::read_body(res, limit).await.map_err(ReqwestError)?;
+        let mut content = Content::new(bytes, name, self.mime.as_deref());
+        if truncated {
+            self.truncation = Some(Truncation::Truncated);
+            // A cut pdf or image can not be read, it is neither parsed, indexed
+            // nor archived
+            if content.get_kind().is_binary() {
+                return Ok(());
+            }
+            content = content.into_partial();
+        }
+        self.content = Some(Arc::new(content));
+        Ok(())
+    }
+
     async fn fetch(&mut self) -> Result<(), PageError> {
-        let res = self
-            .client.lock().await
-            .get(&self.url.to_string())
-            .send()
-            .await
-            .map_err(ReqwestError)?;
-
-    
+        if let Some(head) = download::preflight(&self.url, self.download.preflight, &self.client).await {
+            if !self.accept_head(head) {
+                return Ok(());
+            }
+        }
+
+        let res = self.client.get(&self.url).send().await.map_err(ReqwestError)?;
+
         if Url::parse(res.url().to_string())
             .map_err(|_| PageError::InvalidFinalUrl)?
             .is_cas()
@@ -77,38 +227,68 @@ impl Page {
         } else {
             // get links from the page
             self.status = res.status().as_u16();
-            let bytes = res.bytes().await.map_err(ReqwestError)?;
-            self.content = Some(Content::new(bytes.into_iter().collect(), self.url.get_file_name()));
-        }
-
-        self.links = if let Some(content) = &self.content {
-            content.get_links(self.url.clone())
-        } else {
-            HashSet::<Url>::new()
-        };
-
-        if let Some(content) = self.content.as_ref() {
-            content.publish(&[self.url.clone()]);
-            content.save(self.url.clone()).await;
+            if res.status().is_redirection() {
+                self.redirect = res
+                    .headers()
+                    .get(LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .and_then(|location| self.url.join(location).ok())
+                    .filter(|location| location.get_host() != self.url.get_host());
+            }
+            for value in res.headers().get_all("X-Robots-Tag") {
+                if let Ok(value) = value.to_str() {
+                    self.robots = self.robots.merge(Directives::parse_header(value));
+                }
+            }
+            self.read_content(res).await?;
         }
-
-        self.links.remove(&self.url);
-
         Ok(())
     }
 
-    fn is_cas(&self) -> bool {
-        self.url.is_cas()
+    /// Read the links and the directives of the downloaded content
+    pub fn parse(&mut self) {
+        if let Some(content) = &self.content {
+            let parsed = content.parse(self.url.clone());
+            self.links = parsed.links;
+            self.robots = self.robots.merge(parsed.robots);
+            // A canonical on another host is only a hint, the page is kept
+            self.canonical = parsed
+                .canonical
+                .filter(|_| !self.keep_variant)
+                .map(|canonical| self.canonicalizer.canonicalize(&canonical))
+                .filter(|canonical| canonical.get_hash() != self.url.get_hash())
+                .filter(|canonical| canonical.get_host() == self.url.get_host());
+            self.alternates = parsed.alternates;
+            self.feed_items = parsed.feed_items;
+        }
+        if let Some(redirect) = &self.redirect {
+            let position = self.links.len();
+            self.links.push(Link::new(redirect.clone(), LinkSource::Redirect, position));
+        }
+        self.robots = self.robots_policy.apply(self.robots);
+
+        let ignore_nofollow = self.robots_policy.ignore_nofollow;
+        if self.robots.nofollow {
+            self.links.clear();
+            self.feed_items.clear();
+        }
+        self.links.retain(|link| {
+            link.url != self.url && (ignore_nofollow || !link.rel.contains(Rel::NOFOLLOW))
+        });
     }
 
     pub async fn login_cas(&mut self) -> Result<(), PageError> {
+        // One login at a time, the pages waiting for it are fetched again afterwards
+        let client = self.client.clone();
+        let _login = client.lock_login().await;
+
         // Pull the current page and get the execution
-        let res = self
-            .client.lock().await
-            .get(&self.url.to_string())
-            .send()
-            .await
-            .map_err(ReqwestError)?;
+        let res = client.get(&self.url).send().await.map_err(ReqwestError)?;
+        if !Url::parse(res.url().to_string()).map_err(|_| InvalidFinalUrl)?.is_cas() {
+            // Logged in by another page meanwhile
+            self.status = res.status().as_u16();
+            return self.read_content(res).await;
+        }
 
         let execution = if let Ok(content) = res.text().await {
             if !content.contains("name=\"execution\" value=\"") {
@@ -138,41 +318,26 @@ impl Page {
             read_password().unwrap()
         });
 
-        let req = self.client
-                .lock().await
-                .post(self.url.to_string())
-                .header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Safari/537.36")
-                .header("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8")
-                .header("Accept-Language", "en-US,en;q=0.5")
-                .header("Accept-Encoding", "gzip, deflate, br")
-                .header("Content-Type", "application/x-www-form-urlencoded")
-                .header("Origin", "https://cas.insa-rouen.fr")
-                .header("Connection", "keep-alive")
-                .header("Referer", &*urlencoding::encode(self.url.to_string().as_str()))
-                .header("Cookie", "org.springframework.web.servlet.i18n.CookieLocaleResolver.LOCALE=en-US")
-                .header("Upgrade-Insecure-Requests", "1")
-                .header("Sec-Fetch-Dest", "document")
-                .header("Sec-Fetch-Mode", "navigate")
-                .header("Sec-Fetch-Site", "same-origin")
-                .header("Sec-Fetch-User", "?1")
-                .form(&[
-                    ("username", username.as_str()),
-                    ("password", password.as_str()),
-                    ("execution", execution.as_str()),
-                    ("_eventId", "submit"),
-                    ("geolocation", ""),
-                    ("submit", "Login"),
-                ]).build().unwrap();
-
-        let res = self.client.lock().await.execute(req).await.map_err(ReqwestError)?;
+        // The other headers are the ones of the client profile
+        let req = client
+            .post(&self.url)
+            .header(ORIGIN, self.url.get_root().trim_end_matches('/'))
+            .header(REFERER, self.url.to_string())
+            .form(&[
+                ("username", username.as_str()),
+                ("password", password.as_str()),
+                ("execution", execution.as_str()),
+                ("_eventId", "submit"),
+                ("geolocation", ""),
+                ("submit", "Login"),
+            ]);
+        let res = req.send().await.map_err(ReqwestError)?;
 
         if !res.status().is_success() {
             return Err(FailedToLogin);
         }
-        self.content = Some(Content::new(
-            res.bytes().await.into_iter().flatten().collect(),
-            self.url.get_file_name(),
-        ));
+        self.status = res.status().as_u16();
+        self.read_content(res).await?;
         print_progress_bar_final_info("CAS", "Login successful", Color::Green, Style::Bold);
         Ok(())
     }
@@ -185,19 +350,216 @@ impl Page {
         &self.url
     }
 
-    pub fn get_content(&self) -> Option<&Content> {
+    pub fn get_content(&self) -> Option<&Arc<Content>> {
         self.content.as_ref()
     }
+
+    /// Take the content to extract its text, index it and archive it
+    pub fn take_content(&mut self) -> Option<Arc<Content>> {
+        self.content.take()
+    }
+
+    /// Get the texts of the links pointing to the page
+    pub fn get_anchors(&self) -> &[String] {
+        &self.anchors
+    }
+
+    /// Get what a feed says about the page
+    pub fn get_feed_entry(&self) -> Option<&FeedEntry> {
+        self.feed_entry.as_ref()
+    }
+
+    /// The page is indexed, unless it is a variant of its canonical or a resource
+    pub fn is_indexable(&self) -> bool {
+        let is_resource = self.content.as_ref().is_none_or(|content| content.is_resource());
+        !self.robots.noindex && self.canonical.is_none() && !is_resource
+    }
+
+    pub fn is_archivable(&self) -> bool {
+        self.content.is_some() && !self.robots.noarchive
+    }
+
+    pub fn get_links(&self) -> &[Link] {
+        &self.links
+    }
+
+    pub fn get_robots(&self) -> Directives {
+        self.robots
+    }
+
+    /// Get the canonical url of the page if it is a variant of another page
+    pub fn get_canonical(&self) -> Option<&Url> {
+        self.canonical.as_ref()
+    }
+
+    /// Get the versions of the page in other languages
+    pub fn get_alternates(&self) -> &[Alternate] {
+        &self.alternates
+    }
+
+    /// Get the `Content-Type` header of the page
+    pub fn get_mime(&self) -> Option<&str> {
+        self.mime.as_deref()
+    }
+
+    /// Get the announced length of the body
+    pub fn get_length(&self) -> Option<u64> {
+        self.length
+    }
+
+    /// Get whether the body is incomplete because of its size limit
+    pub fn get_truncation(&self) -> Option<Truncation> {
+        self.truncation
+    }
+
+    /// Get the items of the page when it is a RSS or Atom feed
+    pub fn get_feed_items(&self) -> &[FeedItem] {
+        &self.feed_items
+    }
+}
+
+/// Number of fetched pages over time, to report the pages per second
+struct Throughput {
+    started: Instant,
+    pages: usize,
+    /// Start of the current window and its number of pages
+    window: (Instant, usize),
+}
+
+impl Throughput {
+    fn new() -> Self {
+        let now = Instant::now();
+        Throughput {
+            started: now,
+            pages: 0,
+            window: (now, 0),
+        }
+    }
+
+    /// Count a fetched page, the pages per second of the window are returned
+    /// when it is complete
+    fn add_page(&mut self) -> Option<f64> {
+        self.pages += 1;
+        self.window.1 += 1;
+        if self.window.1 < THROUGHPUT_WINDOW {
+            return None;
+        }
+        let rate = self.window.1 as f64 / self.window.0.elapsed().as_secs_f64().max(f64::EPSILON);
+        self.window = (Instant::now(), 0);
+        Some(rate)
+    }
+
+    /// Get the pages per second since the start
+    fn rate(&self) -> f64 {
+        self.pages as f64 / self.started.elapsed().as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// What the scheduler of `UrlCollection::fetch` waits for
+enum Event {
+    Fetched(Option<Box<Fetched>>),
+    Discovered(Option<Discovery>),
+    /// The first delayed url can be fetched
+    Due,
+    Fetcher(Result<OwnedSemaphorePermit, AcquireError>),
+}
+
+/// Why an url is fetched or skipped, decided without any request
+#[derive(Debug)]
+pub enum Verdict<'a> {
+    /// The url is already known
+    Duplicate,
+    /// Medias are not fetched
+    Media,
+    Scope(Decision<'a>),
+    /// Disallowed by the cached robots.txt of the host
+    Robots,
+    Trap(Trap),
+}
+
+impl Verdict<'_> {
+    pub fn is_accepted(&self) -> bool {
+        matches!(self, Verdict::Scope(decision) if decision.is_accepted())
+    }
+}
+
+impl std::fmt::Display for Verdict<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Verdict::Duplicate => write!(f, "already known"),
+            Verdict::Media => write!(f, "media"),
+            Verdict::Scope(decision) => write!(f, "{}", decision),
+            Verdict::Robots => write!(f, "disallowed by robots.txt"),
+            Verdict::Trap(trap) => write!(f, "trap: {}", trap),
+        }
+    }
 }
 
 pub struct UrlCollection {
     to_fetch: VecDeque<Url>,
     known_url_hash: HashSet<u64>,
-    client: Arc<Mutex<Client>>,
+    client: HttpClient,
     #[cfg(feature = "graph")]
     last_fetch: Vec<(Url, Url)>,
     i: usize,
     to_save: Vec<(Url, u16)>,
+    robots_policy: RobotsPolicy,
+    robots_txt: RobotsCache,
+    scope: Scope,
+    /// The medias embedded in the pages are fetched to archive complete pages
+    archival: bool,
+    /// Urls embedded in the pages, the archival mode fetches them even if they
+    /// are medias
+    embedded_url_hash: HashSet<u64>,
+    canonicalizer: Arc<Canonicalizer>,
+    traps: TrapDetector,
+    /// Roots whose sitemaps have been added to the urls to fetch
+    sitemap_roots: HashSet<String>,
+    /// Urls waiting for the robots.txt and the sitemaps of their host, by root
+    parked: HashMap<String, Vec<Url>>,
+    /// Time from which the next page of a host with a `Crawl-delay` can be
+    /// fetched, by root
+    host_slots: HashMap<String, Instant>,
+    /// Urls waiting for the `Crawl-delay` of their host, by time of their slot
+    delayed: BinaryHeap<Reverse<(Instant, Url)>>,
+    /// Delayed urls whose slot has come, they are fetched first
+    ready: VecDeque<Url>,
+    sitemap_hints: HashMap<u64, SitemapHint>,
+    /// Fetch times of the pages listed by sitemaps, to fetch them again when they
+    /// change
+    recrawls: HashMap<u64, Recrawl>,
+    /// Lines of `recrawl.csv` for the pages fetched since the last save
+    recrawls_to_save: Vec<String>,
+    /// Texts of the links pointing to the urls, saved to `anchors.csv`. The texts of
+    /// the fetched urls are kept to publish the whole list when a text is added.
+    anchors: HashMap<u64, Vec<String>>,
+    /// Hashes of the fetched urls, their new anchor texts and feed entries are
+    /// published as updates
+    published_url_hash: HashSet<u64>,
+    /// Fetched urls with new anchor texts
+    anchors_to_update: HashMap<u64, Url>,
+    /// Urls deduplicated into a canonical url, by hash of the canonical url
+    variants: HashMap<u64, Vec<Url>>,
+    /// Canonical urls with new variants
+    variants_to_update: HashMap<u64, Url>,
+    /// Fetched urls that are not indexed, the variants of the canonical ones are
+    /// indexed in their place
+    unindexed_url_hash: HashSet<u64>,
+    /// First variant of the canonical urls not fetched yet, by hash of the canonical
+    /// url, it is indexed if its canonical ends up not indexed
+    fallbacks: HashMap<u64, Url>,
+    /// Variants indexed in place of their canonical
+    kept_variants: HashSet<u64>,
+    /// What the feeds say about their items, by hash of the item url
+    feed_entries: HashMap<u64, FeedEntry>,
+    /// Fetched urls with a new feed entry
+    feed_entries_to_update: HashMap<u64, Url>,
+    download: Arc<DownloadConfig>,
+    /// Lines of `truncated.csv` for the pages over their size limit
+    truncated_to_save: Vec<String>,
+    /// Updates of the published documents waiting for the indexer
+    index_updates: Vec<IndexUpdate>,
+    pipeline: PipelineConfig,
 }
 
 impl Default for UrlCollection {
@@ -205,11 +567,40 @@ impl Default for UrlCollection {
         UrlCollection {
             to_fetch: VecDeque::with_capacity(2 * 1024 * 1024),
             known_url_hash: HashSet::with_capacity(7 * 1024 * 1024),
-            client: Arc::new(Mutex::new(ClientBuilder::new().cookie_store(true).timeout(Duration::from_secs(2)).build().unwrap())),
+            client: HttpClient::default(),
             i: 0,
             #[cfg(feature = "graph")]
             last_fetch: Vec::new(),
             to_save: Vec::new(),
+            robots_policy: RobotsPolicy::default().with_env(),
+            robots_txt: RobotsCache::new(),
+            scope: Scope::default(),
+            archival: false,
+            embedded_url_hash: HashSet::new(),
+            canonicalizer: Arc::new(Canonicalizer::new(&CanonicalConfig::default())),
+            traps: TrapDetector::default(),
+            sitemap_roots: HashSet::new(),
+            parked: HashMap::new(),
+            host_slots: HashMap::new(),
+            delayed: BinaryHeap::new(),
+            ready: VecDeque::new(),
+            sitemap_hints: HashMap::new(),
+            recrawls: HashMap::new(),
+            recrawls_to_save: Vec::new(),
+            anchors: HashMap::new(),
+            published_url_hash: HashSet::new(),
+            anchors_to_update: HashMap::new(),
+            variants: HashMap::new(),
+            variants_to_update: HashMap::new(),
+            unindexed_url_hash: HashSet::new(),
+            fallbacks: HashMap::new(),
+            kept_variants: HashSet::new(),
+            feed_entries: HashMap::new(),
+            feed_entries_to_update: HashMap::new(),
+            download: Arc::default(),
+            truncated_to_save: Vec::new(),
+            index_updates: Vec::new(),
+            pipeline: PipelineConfig::default(),
         }
     }
 }
@@ -219,35 +610,223 @@ impl UrlCollection {
         UrlCollection::default()
     }
 
-    /// Add a not fetched url with a referer
-    pub fn add_url_to_fetch_with_referer(&mut self, _from: Url, to: Url, _status: u16) {
-        if !self.known_url_hash.contains(&to.get_hash()) {
-            self.known_url_hash.insert(to.get_hash());
-            self.to_fetch.push_back(to.clone());
+    /// Create the collection with the scope, the canonicalization, the trap
+    /// limits, the download limits, the client profile, the concurrency of the
+    /// pipeline, the robots directives to ignore and the mode of the configuration
+    pub fn with_config(config: &Config) -> Result<Self, ConfigError> {
+        Ok(UrlCollection {
+            scope: Scope::new(&config.scope)?,
+            archival: config.archival,
+            canonicalizer: Arc::new(Canonicalizer::new(&config.canonical)),
+            traps: TrapDetector::new(&config.traps),
+            download: Arc::new(config.download.clone()),
+            client: HttpClient::new(&config.client)?,
+            pipeline: config.pipeline.clone(),
+            robots_policy: config.robots.with_env(),
+            ..UrlCollection::default()
+        })
+    }
+
+    /// Set the robots directives to ignore, for internal crawls of our own sites
+    pub fn set_robots_policy(&mut self, robots_policy: RobotsPolicy) {
+        self.robots_policy = robots_policy;
+    }
+
+    /// Add a not fetched url with a referer, the text of the link is kept
+    /// to be indexed with the target
+    pub fn add_url_to_fetch_with_referer(&mut self, from: Url, mut to: Link, _status: u16) {
+        to.url = self.canonicalize(&to.url);
+        if self.archival && to.is_embedded() {
+            self.embedded_url_hash.insert(to.url.get_hash());
         }
+        self.push_to_fetch(&to.url);
+
+        let texts = [Some(&to.text), to.title.as_ref()];
+        self.add_anchors(&to.url, texts.into_iter().flatten());
         #[cfg(feature = "graph")]
-        self.last_fetch.push((from, to));
+        self.last_fetch.push((from, to.url));
+        #[cfg(not(feature = "graph"))]
+        let _ = from;
+    }
+
+    /// Add texts of links pointing to an url
+    fn add_anchors<'a>(&mut self, url: &Url, texts: impl Iterator<Item = &'a String>) {
+        let hash = url.get_hash();
+        for text in texts.filter(|text| !text.is_empty()) {
+            let anchors = self.anchors.entry(hash).or_default();
+            if anchors.len() < MAX_ANCHORS && !anchors.contains(text) {
+                anchors.push(text.clone());
+                if self.published_url_hash.contains(&hash) {
+                    self.anchors_to_update.insert(hash, url.clone());
+                }
+            }
+        }
+    }
+
+    /// Deduplicate a fetched url into its canonical url: the variant and the
+    /// texts of its links are recorded with the canonical document. The variant is
+    /// indexed instead when its canonical is not indexed or trapped.
+    fn add_variant(&mut self, url: &Url, canonical: &Url) {
+        let hash = canonical.get_hash();
+        let is_new = !self.known_url_hash.contains(&hash);
+        if self.unindexed_url_hash.contains(&hash) || (is_new && !self.admit(canonical)) {
+            self.keep_variant(url.clone(), hash);
+            return;
+        }
+        if is_new {
+            self.to_fetch.push_back(canonical.clone());
+        }
+        self.fallbacks.entry(hash).or_insert_with(|| url.clone());
+
+        let variants = self.variants.entry(hash).or_default();
+        if variants.len() < MAX_VARIANTS && !variants.contains(url) {
+            variants.push(url.clone());
+            self.variants_to_update.insert(hash, canonical.clone());
+        }
+        let texts = self.anchors.get(&url.get_hash()).cloned().unwrap_or_default();
+        self.add_anchors(canonical, texts.iter());
+    }
+
+    /// Record whether a fetched or skipped url is indexed, the variant waiting for
+    /// it is indexed in its place if it is not
+    fn resolve_canonical(&mut self, url: &Url, indexed: bool) {
+        let hash = url.get_hash();
+        let variant = self.fallbacks.remove(&hash);
+        if indexed {
+            return;
+        }
+        self.unindexed_url_hash.insert(hash);
+        if let Some(variant) = variant {
+            self.keep_variant(variant, hash);
+        }
+    }
+
+    /// Fetch a variant again to index it in place of its canonical url
+    fn keep_variant(&mut self, variant: Url, canonical_hash: u64) {
+        if self.kept_variants.insert(variant.get_hash()) {
+            self.variants_to_update.remove(&canonical_hash);
+            self.to_fetch.push_front(variant);
+        }
+    }
+
+    /// Add the item of a feed: a new item is fetched before the other urls to keep
+    /// the news fresh, and its entry is published with its document. A fetched
+    /// item whose date changed is fetched again the same way.
+    fn add_feed_item(&mut self, item: &FeedItem) {
+        let url = self.canonicalize(&item.url);
+        let hash = url.get_hash();
+        if self.admit(&url) {
+            self.to_fetch.push_front(url.clone());
+        }
+        let previous = self.feed_entries.insert(hash, item.entry.clone());
+        if previous.as_ref() == Some(&item.entry) {
+            return;
+        }
+        if self.published_url_hash.contains(&hash) {
+            let is_updated = item.entry.date.is_some()
+                && previous.is_some_and(|previous| previous.date != item.entry.date);
+            if is_updated {
+                self.to_fetch.push_front(url);
+            } else {
+                self.feed_entries_to_update.insert(hash, url);
+            }
+        }
+    }
+
+    /// Get the urls deduplicated into a canonical url
+    pub fn get_variants(&self, canonical: &Url) -> &[Url] {
+        self.variants
+            .get(&canonical.get_hash())
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Take the anchor texts of an url to fetch it, it is published afterwards
+    fn take_anchors(&mut self, url: &Url) -> Vec<String> {
+        let hash = url.get_hash();
+        self.published_url_hash.insert(hash);
+        self.anchors.get(&hash).cloned().unwrap_or_default()
     }
 
     /// Add a not fetched url
     pub fn add_url_to_fetch(&mut self, url: Url) {
-        if !self.known_url_hash.contains(&url.get_hash()) {
-            self.known_url_hash.insert(url.get_hash());
+        self.push_to_fetch(&url);
+    }
+
+    /// Get the canonical form of an url, the one that is hashed and fetched
+    pub fn canonicalize(&self, url: &Url) -> Url {
+        self.canonicalizer.canonicalize(url)
+    }
+
+    /// Add an url to the urls to fetch if it is new and not trapped
+    fn push_to_fetch(&mut self, url: &Url) {
+        let url = &self.canonicalize(url);
+        if self.admit(url) {
             self.to_fetch.push_back(url.clone());
         }
     }
 
+    /// Make a canonical url known, `true` if it is new and not trapped. The urls
+    /// out of the scope are not counted in the budgets of the traps.
+    fn admit(&mut self, url: &Url) -> bool {
+        self.known_url_hash.insert(url.get_hash())
+            && (!self.scope.decide(url).is_accepted() || self.traps.check(url).is_none())
+    }
+
+    /// Get the patterns of the urls rejected by the trap heuristics
+    pub fn get_trapped(&self) -> Vec<TrappedPattern> {
+        self.traps.get_trapped()
+    }
+
+    /// Get the robots.txt of the host of the url, `None` if it has not been fetched yet
+    pub fn get_robots_txt(&self, url: &Url) -> Option<&RobotsTxt> {
+        self.robots_txt.get_cached(url)
+    }
+
     /// Get the number of links
     pub fn get_links_count(&self) -> usize {
         self.known_url_hash.len()
     }
 
+    /// Check an url the way `fetch` does before fetching it, only the cached
+    /// robots.txt are used
+    pub fn check(&self, url: &Url) -> Verdict<'_> {
+        if (url.get_uri_scheme() == UriScheme::Http || url.get_uri_scheme() == UriScheme::Https)
+            && url.is_media()
+            && !self.embedded_url_hash.contains(&url.get_hash())
+        {
+            return Verdict::Media;
+        }
+        let decision = self.scope.decide(url);
+        if decision.is_accepted()
+            && !self.robots_policy.ignore_robots_txt
+            && self.get_robots_txt(url).is_some_and(|robots_txt| !robots_txt.is_allowed(url))
+        {
+            return Verdict::Robots;
+        }
+        Verdict::Scope(decision)
+    }
+
+    /// Check an url as if it was added to the urls to fetch, it is known afterwards
+    pub fn check_new(&mut self, url: &Url) -> Verdict<'_> {
+        let url = &self.canonicalize(url);
+        if !self.known_url_hash.insert(url.get_hash()) {
+            return Verdict::Duplicate;
+        }
+        if self.scope.decide(url).is_accepted() {
+            if let Some(trap) = self.traps.check(url) {
+                return Verdict::Trap(trap);
+            }
+        }
+        self.check(url)
+    }
+
     /// Start the fetch
     pub async fn fetch(&mut self) -> Result<(), PageError> {
 
         init_progress_bar(self.get_links_count());
         set_progress_bar_action("Fetching", Color::Green, Style::Bold);
-        let mut ongoing_requests = vec![];
+        let mut throughput = Throughput::new();
 
         let package_i = AtomicU32::new(1);
         
@@ -258,7 +837,7 @@ impl UrlCollection {
             package_string = format!("data/package-{}.7z", package_i.load(Ordering::Acquire));
         }
             // Start a new thread for the compression
-        let compression_thread = std::thread::spawn(move || {
+        std::thread::spawn(move || {
             // Ensure the package directory exists
             let _ = fs::create_dir(format!("data/package-{}", package_i.load(Ordering::Acquire)));
             
@@ -266,7 +845,7 @@ impl UrlCollection {
                 // Count the number of files and their size
                 let mut size = 0;
                 let mut n_files = 0;
-                package_string = format!("data/package-{}", package_i.load(Ordering::Acquire));
+                let package_string = format!("data/package-{}", package_i.load(Ordering::Acquire));
                 if let Ok(entries) = fs::read_dir(&package_string) {
                     for entry in entries.flatten() {
                         if let Ok(metadata) = entry.metadata() {
@@ -281,90 +860,319 @@ impl UrlCollection {
                 // Compress the files
                 if size > 512 * 1024 * 1024 || n_files > 1_000 {
                     package_i.fetch_add(1, Ordering::SeqCst);
-                    fs::create_dir(&format!("data/package-{}", package_i.load(Ordering::Acquire))).unwrap();
-                    let _ = sevenz_rust::compress_to_path(&package_string, &format!("data/package-{}.7z", package_i.load(Ordering::Acquire)));
+                    fs::create_dir(format!("data/package-{}", package_i.load(Ordering::Acquire))).unwrap();
+                    let _ = sevenz_rust::compress_to_path(&package_string, format!("data/package-{}.7z", package_i.load(Ordering::Acquire)));
                     fs::remove_dir_all(&package_string).unwrap();
                 }
                 std::thread::sleep(Duration::from_secs(5));
             }
         });
 
-        while !self.to_fetch.is_empty() || !ongoing_requests.is_empty() {
-            if ongoing_requests.len() < CONCURRENT_REQUESTS {
-                while let Some(url) = self.to_fetch.pop_front() {
-                    let url = url.clone();
-                    self.known_url_hash.insert(url.get_hash());
-                    self.i += 1;
-                    if (url.get_uri_scheme() == UriScheme::Http
-                        || url.get_uri_scheme() == UriScheme::Https)
-                        && url.is_media()
-                        || !url.is_allowed()
-                        || url.is_black_listed()
-                        || url.to_string().contains("mailto")
-                        || url.to_string().ends_with("logout")
-
-                    {
-                        print_progress_bar_info(
-                            "Skip",
-                            &url.to_string(),
-                            Color::Yellow,
-                            Style::Bold,
-                        );
-                        self.to_save.push((url.clone(), 0));
-                        continue;
+        let mut pipeline = Pipeline::start(&self.pipeline, self.fetch_options());
+        let fetchers = pipeline.fetchers();
+        // Pages sent to the pipeline whose result has not come back yet
+        let mut in_flight = 0;
+        loop {
+            let due = self.delayed.peek().map_or_else(Instant::now, |Reverse((at, _))| *at);
+            let has_jobs = !self.to_fetch.is_empty() || !self.ready.is_empty();
+            // The results are read first so that the parsers are never blocked
+            let event = tokio::select! {
+                biased;
+                fetched = pipeline.results.recv(), if in_flight > 0 => Event::Fetched(fetched.map(Box::new)),
+                discovery = pipeline.discovered.recv(), if !self.parked.is_empty() => Event::Discovered(discovery),
+                _ = tokio::time::sleep_until(due.into()), if !self.delayed.is_empty() => Event::Due,
+                permit = Arc::clone(&fetchers).acquire_owned(), if has_jobs => Event::Fetcher(permit),
+                else => break,
+            };
+            let fetched = match event {
+                Event::Fetched(Some(fetched)) => *fetched,
+                Event::Discovered(Some(discovery)) => {
+                    self.add_discovery(discovery);
+                    continue;
+                }
+                Event::Due => {
+                    while let Some(Reverse((at, _))) = self.delayed.peek() {
+                        if *at > Instant::now() {
+                            break;
+                        }
+                        if let Some(Reverse((_, url))) = self.delayed.pop() {
+                            self.ready.push_back(url);
+                        }
                     }
-                    ongoing_requests.push(Box::pin(Page::new(url.clone(), Arc::clone(&self.client))));
-                    if ongoing_requests.len() >= CONCURRENT_REQUESTS {
-                        break;
+                    continue;
+                }
+                Event::Fetcher(Ok(permit)) => {
+                    if let Some(job) = self.next_job(&pipeline) {
+                        pipeline.fetch(permit, job);
+                        in_flight += 1;
                     }
+                    continue;
                 }
+                Event::Fetched(None) | Event::Discovered(None) | Event::Fetcher(Err(_)) => break,
+            };
+            in_flight -= 1;
+            inc_progress_bar();
+            if let Some(rate) = throughput.add_page() {
+                print_progress_bar_info(
+                    "Speed",
+                    &format!(
+                        "{:.1} pages/s ({:.1} pages/s overall), extraction: {}",
+                        rate,
+                        throughput.rate(),
+                        pipeline.extraction_stats()
+                    ),
+                    Color::Cyan,
+                    Style::Normal,
+                );
             }
-            std::thread::sleep(Duration::from_millis(1));
-
-            if ongoing_requests.is_empty() {
-                print_progress_bar_info("Empty queue", "No request", Color::Cyan, Style::Normal);
-                continue;
+            match fetched {
+                Ok(page) => self.add_page(&page),
+                Err((url, err)) => {
+                    print_progress_bar_info(
+                        "Error",
+                        &format!("{} ({:?})", url.to_display_string(), err),
+                        Color::Red,
+                        Style::Bold,
+                    );
+                    self.resolve_canonical(&url, false);
+                }
+            }
+            if self.to_save.len() > 300 {
+                self.save_graph();
+                pipeline.update_index(self.take_index_updates()).await;
             }
+        }
+        finalize_progress_bar();
+        println!(
+            "{} pages in {:.0?}, {:.1} pages/s, extraction: {}",
+            throughput.pages,
+            throughput.started.elapsed(),
+            throughput.rate(),
+            pipeline.extraction_stats()
+        );
+        self.save_graph();
+        pipeline.update_index(self.take_index_updates()).await;
+        pipeline.close().await;
+        Ok(())
+    }
 
-            let (page, _, remaining_requests) = futures::future::select_all(ongoing_requests).await;
-            ongoing_requests = remaining_requests;
-            inc_progress_bar();
+    /// What the pages of the crawl are fetched with
+    fn fetch_options(&self) -> FetchOptions {
+        FetchOptions {
+            robots_policy: self.robots_policy,
+            canonicalizer: Arc::clone(&self.canonicalizer),
+            download: Arc::clone(&self.download),
+            client: self.client.clone(),
+        }
+    }
 
-            let page = if let Ok(page) = page {
-                page
-            } else {
+    /// Take the next url to fetch, the urls refused by the scope or by the
+    /// robots.txt are only recorded
+    fn next_job(&mut self, pipeline: &Pipeline) -> Option<FetchJob> {
+        if let Some(url) = self.ready.pop_front() {
+            return Some(self.job(url));
+        }
+        while let Some(url) = self.to_fetch.pop_front() {
+            self.known_url_hash.insert(url.get_hash());
+            // Why the url is rejected, if it is by its robots.txt. Parking does not change it
+            let rejection = {
+                let verdict = self.check(&url);
+                (!verdict.is_accepted()).then(|| (matches!(verdict, Verdict::Robots), verdict.to_string()))
+            };
+            // The robots.txt decides once it is fetched
+            let needs_host = matches!(rejection, None | Some((true, _)));
+            if needs_host && self.park(&url, pipeline) {
+                continue;
+            }
+            self.i += 1;
+            if let Some((robots, reason)) = rejection {
+                let action = match robots {
+                    true => "Robots",
+                    false => "Skip",
+                };
                 print_progress_bar_info(
-                    "Error",
-                    &format!("{:?}", page.err().unwrap()),
-                    Color::Red,
+                    action,
+                    &format!("{} ({})", url.to_display_string(), reason),
+                    Color::Yellow,
                     Style::Bold,
                 );
+                self.resolve_canonical(&url, false);
+                self.to_save.push((url, 0));
+                continue;
+            }
+            if let Some(slot) = self.reserve_slot(&url) {
+                self.delayed.push(Reverse((slot, url)));
                 continue;
-            };
-
-            page.links.iter().for_each(|link| {
-                self.add_url_to_fetch_with_referer(
-                    page.url.clone(),
-                    link.clone(),
-                    page.get_status(),
-                );
-            });
-            self.to_save.push((page.url.clone(), page.get_status()));
-            if self.to_save.len() > 300 {
-                self.save_graph();
             }
+            return Some(self.job(url));
+        }
+        None
+    }
 
-            set_progress_bar_max(self.get_links_count());
+    /// Get the job of an url to fetch with what is known about it
+    fn job(&mut self, url: Url) -> FetchJob {
+        let anchors = self.take_anchors(&url);
+        let feed_entry = self.feed_entries.get(&url.get_hash()).cloned();
+        let keep_variant = self.kept_variants.contains(&url.get_hash());
+        FetchJob {
+            url,
+            anchors,
+            feed_entry,
+            keep_variant,
+        }
+    }
+
+    /// Reserve the next slot of the host of the url when its robots.txt has a
+    /// `Crawl-delay`, `None` if the url can be fetched now
+    fn reserve_slot(&mut self, url: &Url) -> Option<Instant> {
+        if self.robots_policy.ignore_robots_txt {
+            return None;
+        }
+        let delay = self.robots_txt.get_cached(url)?.get_crawl_delay()?.min(MAX_CRAWL_DELAY);
+        let now = Instant::now();
+        let slot = self.host_slots.entry(url.get_root()).or_insert(now);
+        let at = (*slot).max(now);
+        *slot = at + delay;
+        (at > now).then_some(at)
+    }
+
+    /// Park the url while the robots.txt and the sitemaps of its host are fetched,
+    /// the first time the host is seen and when its robots.txt expires
+    fn park(&mut self, url: &Url, pipeline: &Pipeline) -> bool {
+        let root = url.get_root();
+        if let Some(parked) = self.parked.get_mut(&root) {
+            parked.push(url.clone());
+            return true;
+        }
+        let sitemaps = self.sitemap_roots.insert(root.clone());
+        let robots_txt = !self.robots_policy.ignore_robots_txt && !self.robots_txt.is_fresh(url);
+        if !sitemaps && !robots_txt {
+            return false;
+        }
+        pipeline.discover(url.clone(), sitemaps);
+        self.parked.insert(root, vec![url.clone()]);
+        true
+    }
+
+    /// Add the robots.txt and the sitemaps of a host, its parked urls are fetched
+    /// first
+    fn add_discovery(&mut self, discovery: Discovery) {
+        self.robots_txt.insert(discovery.root.clone(), discovery.robots_txt, discovery.robots_ttl);
+        if let Some(entries) = discovery.sitemap_entries {
+            let count = self.get_links_count();
+            self.add_sitemap_entries(entries);
             print_progress_bar_info(
-                "Fetched",
-                &page.get_url().to_string(),
-                Color::Blue,
-                Style::Bold,
+                "Sitemap",
+                &format!("{} new urls from {}", self.get_links_count() - count, discovery.root),
+                Color::Cyan,
+                Style::Normal,
             );
+            set_progress_bar_max(self.get_links_count());
+        }
+        for url in self.parked.remove(&discovery.root).unwrap_or_default().into_iter().rev() {
+            self.to_fetch.push_front(url);
         }
-        finalize_progress_bar();
-        self.save_graph();
-        Ok(())
+    }
+
+    /// Add the links and the feed items of a fetched page
+    fn add_page(&mut self, page: &Page) {
+        // The items are pushed in reverse so that the first one is fetched first
+        for item in page.get_feed_items().iter().rev() {
+            self.add_feed_item(item);
+        }
+        page.links.iter().for_each(|link| {
+            self.add_url_to_fetch_with_referer(
+                page.url.clone(),
+                link.clone(),
+                page.get_status(),
+            );
+        });
+        self.resolve_canonical(&page.url, page.is_indexable());
+        if let Some(canonical) = page.get_canonical() {
+            self.add_variant(&page.url, canonical);
+        }
+        if let Some(truncation) = page.get_truncation() {
+            self.add_truncated(page, truncation);
+        }
+        if let Some(hint) = self.sitemap_hints.get(&page.url.get_hash()) {
+            let fetched = unix_time();
+            let due = hint.changefreq.and_then(|changefreq| changefreq.interval()).map(|interval| fetched + interval.as_secs());
+            self.add_recrawl(Recrawl { url: page.url.clone(), fetched, due });
+        }
+        self.to_save.push((page.url.clone(), page.get_status()));
+
+        set_progress_bar_max(self.get_links_count());
+        print_progress_bar_info(
+            "Fetched",
+            &page.get_url().to_display_string(),
+            Color::Blue,
+            Style::Bold,
+        );
+    }
+
+    /// Log a page over its size limit with what is known about its body
+    fn add_truncated(&mut self, page: &Page, truncation: Truncation) {
+        let length = page.get_length().map(|length| length.to_string()).unwrap_or_default();
+        let mime = page.get_mime().unwrap_or_default();
+        print_progress_bar_info(
+            "Oversized",
+            &format!("{} ({}, {}, {} bytes)", page.get_url().to_display_string(), truncation, mime, length),
+            Color::Yellow,
+            Style::Bold,
+        );
+        self.truncated_to_save.push(format!(
+            "{};{};{};{};{}",
+            page.get_status(),
+            truncation,
+            mime.replace(';', ","),
+            length,
+            page.get_url()
+        ));
+    }
+
+    /// Add the urls of the sitemaps (and of their indexes) to fetch, the urls with
+    /// the highest priority first
+    pub async fn add_sitemaps(&mut self, sitemaps: Vec<Url>) {
+        let entries = sitemap::fetch(sitemaps, &self.client).await;
+        self.add_sitemap_entries(entries);
+    }
+
+    /// Add the urls listed by sitemaps: the urls with a priority over the default
+    /// one are fetched before the others, highest first, and the fetched urls
+    /// that changed since are fetched again
+    fn add_sitemap_entries(&mut self, mut entries: Vec<SitemapEntry>) {
+        entries.sort_by(|a, b| b.hint.priority.total_cmp(&a.hint.priority));
+        let now = unix_time();
+        let mut prioritized = Vec::new();
+        for entry in entries {
+            let url = self.canonicalize(&entry.url);
+            let hash = url.get_hash();
+            if self.recrawls.get(&hash).is_some_and(|recrawl| recrawl.is_stale(&entry.hint, now)) {
+                self.recrawls.remove(&hash);
+                self.to_fetch.push_back(url.clone());
+            } else if self.admit(&url) {
+                match entry.hint.is_prioritized() {
+                    true => prioritized.push(url),
+                    false => self.to_fetch.push_back(url),
+                }
+            }
+            self.sitemap_hints.insert(hash, entry.hint);
+        }
+        for url in prioritized.into_iter().rev() {
+            self.to_fetch.push_front(url);
+        }
+    }
+
+    /// Record the fetch of a page listed by a sitemap
+    fn add_recrawl(&mut self, recrawl: Recrawl) {
+        let due = recrawl.due.map(|due| due.to_string()).unwrap_or_default();
+        self.recrawls_to_save.push(format!("{};{};{}", recrawl.fetched, due, recrawl.url));
+        self.recrawls.insert(recrawl.url.get_hash(), recrawl);
+    }
+
+    /// Get the sitemap hints of an url, `None` if it is not in a sitemap
+    pub fn get_sitemap_hint(&self, url: &Url) -> Option<&SitemapHint> {
+        self.sitemap_hints.get(&self.canonicalize(url).get_hash())
     }
 
     /// Fetch all pages
@@ -378,6 +1186,8 @@ impl UrlCollection {
 
     /// Save the graph to a file
     pub fn save_graph(&mut self) {
+        self.publish_updates();
+
         // Check if the file exists and contains the header
         let mut file_fetcheds = OpenOptions::new()
             .append(true)
@@ -447,9 +1257,101 @@ impl UrlCollection {
             .unwrap();
         #[cfg(feature = "graph")]
         file_edges.write_all(b"\n").unwrap();
+
+        // Write all the anchor texts, they are read back with the urls to fetch
+        let mut file_anchors = File::create("anchors.csv").unwrap();
+        file_anchors.write_all(self.anchors_to_csv().as_bytes()).unwrap();
+
+        // Append the pages over their size limit
+        if !self.truncated_to_save.is_empty() {
+            let mut file_truncated = OpenOptions::new()
+                .append(true)
+                .open("truncated.csv")
+                .unwrap_or_else(|_| {
+                    let mut file = File::create("truncated.csv").unwrap();
+                    file.write_all(b"status;truncation;content_type;length;url\n").unwrap();
+                    file
+                });
+            let lines = self.truncated_to_save.drain(..).collect::<Vec<_>>().join("\n");
+            file_truncated.write_all(lines.as_bytes()).unwrap();
+            file_truncated.write_all(b"\n").unwrap();
+        }
+
+        // Append the fetch times of the pages listed by sitemaps
+        if !self.recrawls_to_save.is_empty() {
+            let mut file_recrawl = OpenOptions::new()
+                .append(true)
+                .open("recrawl.csv")
+                .unwrap_or_else(|_| {
+                    let mut file = File::create("recrawl.csv").unwrap();
+                    file.write_all(b"fetched;due;url\n").unwrap();
+                    file
+                });
+            let lines = self.recrawls_to_save.drain(..).collect::<Vec<_>>().join("\n");
+            file_recrawl.write_all(lines.as_bytes()).unwrap();
+            file_recrawl.write_all(b"\n").unwrap();
+        }
+
+        // Rewrite the trapped patterns
+        let trapped = self.get_trapped();
+        if !trapped.is_empty() {
+            let mut traps_csv = vec![String::from("trap;pattern;rejected")];
+            for trapped in trapped {
+                traps_csv.push(format!("{:?};{};{}", trapped.trap, trapped.pattern, trapped.rejected));
+            }
+            let _ = fs::write("traps.csv", traps_csv.join("\n") + "\n");
+        }
+    }
+
+    /// Queue the new anchor texts, variants and feed entries of the fetched pages
+    /// for the indexer
+    fn publish_updates(&mut self) {
+        let anchors = self
+            .anchors_to_update
+            .drain()
+            .filter_map(|(hash, url)| Some(IndexUpdate::Anchors(url, self.anchors.get(&hash)?.clone())));
+        self.index_updates.extend(anchors);
+        let variants = self
+            .variants_to_update
+            .drain()
+            .filter_map(|(hash, url)| Some(IndexUpdate::Variants(url, self.variants.get(&hash)?.clone())));
+        self.index_updates.extend(variants);
+        let entries = self
+            .feed_entries_to_update
+            .drain()
+            .filter_map(|(hash, url)| Some(IndexUpdate::Feed(url, self.feed_entries.get(&hash)?.clone())));
+        self.index_updates.extend(entries);
+    }
+
+    /// Take the updates of the published documents, to send them to the indexer
+    pub fn take_index_updates(&mut self) -> Vec<IndexUpdate> {
+        std::mem::take(&mut self.index_updates)
     }
 
     /// Load the graph from a file
+    /// Get the lines of `anchors.csv`: the hash of the url and one of its texts
+    fn anchors_to_csv(&self) -> String {
+        let mut csv = String::from("hash;text\n");
+        for (hash, texts) in self.anchors.iter() {
+            for text in texts {
+                csv.push_str(&format!("{:016x};{}\n", hash, text.replace('\n', " ")));
+            }
+        }
+        csv
+    }
+
+    /// Add the anchor texts of the lines of `anchors.csv`
+    fn load_anchors(&mut self, csv: &str) {
+        for line in csv.lines().skip(1) {
+            let Some((hash, text)) = line.split_once(';') else {
+                continue;
+            };
+            if let Ok(hash) = u64::from_str_radix(hash, 16) {
+                self.anchors.entry(hash).or_default().push(text.to_string());
+            }
+        }
+    }
+
     pub async fn load_graph(&mut self) {
         // Check if the files exists
         if File::open("to_fetch.csv").is_err() || File::open("fetcheds.csv").is_err() {
@@ -464,16 +1366,51 @@ impl UrlCollection {
             }
         }
 
-        // Load the fetcheds
+        // Load the fetcheds, the ones with a status have been published
         let fetcheds = std::fs::read_to_string("fetcheds.csv").unwrap();
         for line in fetcheds.lines().skip(1) {
             let mut parts = line.split(';');
-            let url = parts.nth(1).unwrap();
+            let status = parts.next().unwrap_or_default();
+            let url = parts.next().unwrap();
             if let Ok(url) = Url::parse(url) {
-                self.known_url_hash.insert(url.get_hash());
+                let hash = self.canonicalize(&url).get_hash();
+                self.known_url_hash.insert(hash);
+                if status != "0" {
+                    self.published_url_hash.insert(hash);
+                }
                 self.i += 1;
             }
         }
+
+        // Load the anchor texts
+        self.load_anchors(&std::fs::read_to_string("anchors.csv").unwrap_or_default());
+
+        // Load the fetch times of the pages listed by sitemaps, the last line of an
+        // url wins. The pages due are fetched again, the others when their sitemap
+        // says they changed.
+        let recrawls = std::fs::read_to_string("recrawl.csv").unwrap_or_default();
+        for line in recrawls.lines().skip(1) {
+            let mut parts = line.splitn(3, ';');
+            let (Some(fetched), Some(due), Some(url)) = (parts.next(), parts.next(), parts.next()) else {
+                continue;
+            };
+            if let (Ok(fetched), Ok(url)) = (fetched.parse(), Url::parse(url)) {
+                let due = due.parse().ok();
+                self.recrawls.insert(url.get_hash(), Recrawl { url, fetched, due });
+            }
+        }
+        let now = unix_time();
+        let due: Vec<u64> = self
+            .recrawls
+            .iter()
+            .filter(|(_, recrawl)| recrawl.due.is_some_and(|due| due <= now))
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in due {
+            if let Some(recrawl) = self.recrawls.remove(&hash) {
+                self.to_fetch.push_back(recrawl.url);
+            }
+        }
     }
 }
 
@@ -486,19 +1423,267 @@ mod errors {
         NotContainsExecution,
         FailedToLogin,
         InvalidFinalUrl,
+        /// The task of the page panicked
+        Panicked,
     }
 }
 #[cfg(test)]
 mod tests {
-    use reqwest::ClientBuilder;
-
     use super::*;
+    use crate::test_server;
+
+    #[test]
+    fn test_variants() {
+        let mut collection = UrlCollection::new();
+        let from = Url::parse("https://www.example.com/").unwrap();
+        let variant = Url::parse("https://www.example.com/page?sort=asc").unwrap();
+        let canonical = Url::parse("https://www.example.com/page").unwrap();
+        let mut link = Link::new(variant.clone(), crate::link::LinkSource::Text, 0);
+        link.text = String::from("Sorted page");
+        collection.add_url_to_fetch_with_referer(from, link, 200);
+
+        collection.add_variant(&variant, &canonical);
+        collection.add_variant(&variant, &canonical);
+        assert_eq!(collection.get_variants(&canonical), &[variant]);
+        assert_eq!(collection.anchors[&canonical.get_hash()], vec![String::from("Sorted page")]);
+        assert!(collection.known_url_hash.contains(&canonical.get_hash()));
+        assert!(collection.variants_to_update.contains_key(&canonical.get_hash()));
+    }
+
+    #[test]
+    fn test_saved_anchors() {
+        let mut collection = UrlCollection::new();
+        let from = Url::parse("https://www.example.com/").unwrap();
+        let url = Url::parse("https://www.example.com/cours.pdf").unwrap();
+        for text in ["Cours", "Cours;\nPDF"] {
+            let mut link = Link::new(url.clone(), LinkSource::Text, 0);
+            link.text = String::from(text);
+            collection.add_url_to_fetch_with_referer(from.clone(), link, 200);
+        }
+        // Only the urls with texts have an entry, even once fetched
+        collection.take_anchors(&from);
+        assert_eq!(collection.anchors.len(), 1);
+
+        let mut loaded = UrlCollection::new();
+        loaded.load_anchors(&collection.anchors_to_csv());
+        assert_eq!(loaded.anchors[&url.get_hash()], [String::from("Cours"), String::from("Cours; PDF")]);
+    }
+
+    #[test]
+    fn test_canonical_fallback() {
+        let mut collection = UrlCollection::new();
+        let variant = Url::parse("https://www.example.com/page?sort=asc").unwrap();
+        let canonical = Url::parse("https://www.example.com/page").unwrap();
+        collection.add_variant(&variant, &canonical);
+        assert_eq!(collection.to_fetch.pop_front(), Some(canonical.clone()));
+
+        // The canonical is not indexed, the variant is fetched again to be indexed
+        collection.resolve_canonical(&canonical, false);
+        assert_eq!(collection.to_fetch.pop_front(), Some(variant.clone()));
+        assert!(collection.job(variant.clone()).keep_variant);
+        assert!(!collection.variants_to_update.contains_key(&canonical.get_hash()));
+
+        // The other variants of a canonical already not indexed are kept at once
+        let other = Url::parse("https://www.example.com/page?sort=desc").unwrap();
+        collection.add_variant(&other, &canonical);
+        assert_eq!(collection.to_fetch.pop_front(), Some(other));
+        collection.add_variant(&variant, &canonical);
+        assert!(collection.to_fetch.is_empty());
+    }
+
+    #[test]
+    fn test_feed_items() {
+        let mut collection = UrlCollection::new();
+        collection.add_url_to_fetch(Url::parse("https://www.example.com/").unwrap());
+        let item = FeedItem {
+            url: Url::parse("https://www.example.com/news/open-days.html").unwrap(),
+            entry: FeedEntry {
+                title: Some(String::from("Open days")),
+                ..FeedEntry::default()
+            },
+        };
+        collection.add_feed_item(&item);
+        assert_eq!(collection.to_fetch.front(), Some(&item.url));
+        assert_eq!(collection.feed_entries[&item.url.get_hash()], item.entry);
+        assert!(collection.feed_entries_to_update.is_empty());
+
+        // A fetched item whose entry changes is updated
+        collection.take_anchors(&item.url);
+        let updated = FeedItem {
+            entry: FeedEntry {
+                summary: Some(String::from("Come and visit the campus")),
+                ..item.entry.clone()
+            },
+            ..item.clone()
+        };
+        collection.add_feed_item(&updated);
+        assert_eq!(collection.to_fetch.len(), 2);
+        assert!(collection.feed_entries_to_update.contains_key(&item.url.get_hash()));
+
+        // A fetched item whose date changes is fetched again
+        collection.to_fetch.pop_front();
+        let dated = FeedItem {
+            entry: FeedEntry {
+                date: Some(String::from("2024-09-01T12:00:00Z")),
+                ..updated.entry.clone()
+            },
+            ..item.clone()
+        };
+        collection.add_feed_item(&dated);
+        assert_eq!(collection.to_fetch.front(), Some(&item.url));
+        assert_eq!(collection.job(item.url.clone()).feed_entry, Some(dated.entry.clone()));
+        collection.to_fetch.pop_front();
+        collection.add_feed_item(&dated);
+        assert_eq!(collection.to_fetch.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_parked_urls() {
+        // A host without robots.txt nor sitemap
+        let root = format!("http://{}/", test_server::serve("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n").await);
+        let config = Config::parse("[scope]\ndefault = \"include\"").unwrap();
+        let mut collection = UrlCollection::with_config(&config).unwrap();
+        let mut pipeline = Pipeline::start(&PipelineConfig::default(), collection.fetch_options());
+        let (first, second) = (Url::parse(format!("{}a", root)).unwrap(), Url::parse(format!("{}b", root)).unwrap());
+        collection.add_url_to_fetch(first.clone());
+        collection.add_url_to_fetch(second.clone());
+
+        // The urls wait for their host without blocking the scheduler
+        assert!(collection.next_job(&pipeline).is_none());
+        assert_eq!(collection.parked[&first.get_root()], vec![first.clone(), second.clone()]);
+        let discovery = pipeline.discovered.recv().await.unwrap();
+        assert_eq!(discovery.sitemap_entries, Some(Vec::new()));
+        collection.add_discovery(discovery);
+        assert!(collection.parked.is_empty());
+        assert_eq!(collection.next_job(&pipeline).unwrap().url, first);
+        assert_eq!(collection.next_job(&pipeline).unwrap().url, second);
+        pipeline.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_crawl_delay() {
+        let mut collection = UrlCollection::with_config(&Config::parse("[scope]\ndefault = \"include\"").unwrap()).unwrap();
+        let pipeline = Pipeline::start(&PipelineConfig::default(), FetchOptions::default());
+        let (first, second) = (Url::parse("https://www.example.com/a").unwrap(), Url::parse("https://www.example.com/b").unwrap());
+        let robots_txt = RobotsTxt::parse("User-agent: *\nCrawl-delay: 10\n");
+        collection.robots_txt.insert(first.get_root(), robots_txt, Duration::from_secs(60));
+        collection.sitemap_roots.insert(first.get_root());
+        collection.add_url_to_fetch(first.clone());
+        collection.add_url_to_fetch(second.clone());
+
+        // The second page of the host waits for the delay
+        assert_eq!(collection.next_job(&pipeline).unwrap().url, first);
+        assert!(collection.next_job(&pipeline).is_none());
+        let Reverse((at, url)) = collection.delayed.peek().unwrap();
+        assert_eq!(url, &second);
+        assert!(*at > Instant::now() + Duration::from_secs(9));
+
+        collection.ready.push_back(second.clone());
+        assert_eq!(collection.next_job(&pipeline).unwrap().url, second);
+        pipeline.close().await;
+    }
+
+    #[test]
+    fn test_sitemap_entries() {
+        let mut collection = UrlCollection::new();
+        collection.add_url_to_fetch(Url::parse("https://www.example.com/").unwrap());
+        let entry = |url: &str, priority: f32, lastmod: Option<&str>| SitemapEntry {
+            url: Url::parse(url).unwrap(),
+            hint: SitemapHint {
+                lastmod: lastmod.map(String::from),
+                priority,
+                ..SitemapHint::default()
+            },
+        };
+        let fetched = Url::parse("https://www.example.com/fetched").unwrap();
+        collection.known_url_hash.insert(fetched.get_hash());
+        collection.add_recrawl(Recrawl { url: fetched.clone(), fetched: 1_725_148_800, due: None });
+
+        collection.add_sitemap_entries(vec![
+            entry("https://www.example.com/low", 0.1, None),
+            entry("https://www.example.com/high", 0.9, None),
+            entry("https://www.example.com/medium", 0.7, None),
+            entry("https://www.example.com/fetched", 0.5, Some("2024-08-01")),
+        ]);
+        // The prioritized urls come first, the fetched page did not change
+        let urls: Vec<String> = collection.to_fetch.iter().map(Url::to_string).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://www.example.com/high",
+                "https://www.example.com/medium",
+                "https://www.example.com/",
+                "https://www.example.com/low",
+            ]
+        );
+
+        // The fetched page changed
+        collection.add_sitemap_entries(vec![entry("https://www.example.com/fetched", 0.5, Some("2024-10-01"))]);
+        assert_eq!(collection.to_fetch.back(), Some(&fetched));
+        assert!(collection.recrawls.is_empty());
+        assert_eq!(collection.recrawls_to_save, vec![String::from("1725148800;;https://www.example.com/fetched")]);
+    }
+
+    #[test]
+    fn test_traps_after_scope() {
+        let config = Config::parse("[traps]\nmax_query_combinations = 1").unwrap();
+        let mut collection = UrlCollection::with_config(&config).unwrap();
+        for i in 0..3 {
+            collection.add_url_to_fetch(Url::parse(format!("https://www.example.com/search?q={}", i)).unwrap());
+            collection.add_url_to_fetch(Url::parse(format!("https://www.insa-rouen.fr/search?q={}", i)).unwrap());
+        }
+        // The urls out of the scope are kept to be logged, without using the budgets
+        assert_eq!(collection.to_fetch.len(), 4);
+        let trapped = collection.get_trapped();
+        assert_eq!(trapped.len(), 1);
+        assert_eq!(trapped[0].pattern, "https://www.insa-rouen.fr/search\\?*");
+    }
+
+    #[test]
+    fn test_archival_medias() {
+        let from = Url::parse("https://www.insa-rouen.fr/").unwrap();
+        let link = |url: &str, element: &str| {
+            let source = crate::link::LinkSource::Html {
+                element: String::from(element),
+                attribute: String::from("src"),
+            };
+            Link::new(Url::parse(url).unwrap(), source, 0)
+        };
+        let css = crate::link::LinkSource::Css { rule: String::from("url") };
+        let links = [
+            link("https://www.insa-rouen.fr/logo.png", "img"),
+            Link::new(Url::parse("https://www.insa-rouen.fr/font.woff2").unwrap(), css, 0),
+            link("https://www.insa-rouen.fr/archive.zip", "a"),
+        ];
+        for archival in [false, true] {
+            let mut collection = UrlCollection::with_config(&Config { archival, ..Config::default() }).unwrap();
+            for link in links.iter() {
+                collection.add_url_to_fetch_with_referer(from.clone(), link.clone(), 200);
+            }
+            let verdicts: Vec<bool> =
+                links.iter().map(|link| matches!(collection.check(&link.url), Verdict::Media)).collect();
+            // Only the medias embedded in the pages are archived
+            assert_eq!(verdicts, [!archival, !archival, true]);
+        }
+    }
+
+    #[test]
+    fn test_throughput() {
+        let mut throughput = Throughput::new();
+        for _ in 1..THROUGHPUT_WINDOW {
+            assert!(throughput.add_page().is_none());
+        }
+        assert!(throughput.add_page().is_some_and(|rate| rate > 0.0));
+        assert_eq!(throughput.window.1, 0);
+        assert_eq!(throughput.pages, THROUGHPUT_WINDOW);
+        assert!(throughput.rate() > 0.0);
+    }
 
     #[tokio::test]
     async fn test_login_cas() {
-        let client = Arc::new(Mutex::new(ClientBuilder::new().cookie_store(true).build().unwrap()));
+        let client = HttpClient::default();
 
-        let mut page = Page::new(Url::parse("https://cas.insa-rouen.fr/cas/login?service=https%3A%2F%2Fmoodle.insa-rouen.fr%2Flogin%2Findex.php%3FauthCAS%3DCAS").unwrap(), client).await.unwrap();
+        let _page = Page::new(Url::parse("https://cas.insa-rouen.fr/cas/login?service=https%3A%2F%2Fmoodle.insa-rouen.fr%2Flogin%2Findex.php%3FauthCAS%3DCAS").unwrap(), client).await.unwrap();
 
     }
 }
@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::{canonical::CanonicalConfig, client::ClientConfig, download::DownloadConfig, pipeline::PipelineConfig, robots::RobotsPolicy, scope::ScopeConfig, trap::TrapConfig};
+
+pub use errors::ConfigError;
+
+/// Default path of the configuration file
+const CONFIG_PATH: &str = "open-finder.toml";
+
+/// Configuration of the crawler, read from `open-finder.toml` or from the file
+/// of the `OPEN_FINDER_CONFIG` environment variable
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Fetch and archive the medias embedded in the pages (images, fonts, videos)
+    /// instead of skipping them, so the archived pages are complete
+    pub archival: bool,
+    pub scope: ScopeConfig,
+    pub canonical: CanonicalConfig,
+    pub traps: TrapConfig,
+    pub download: DownloadConfig,
+    pub client: ClientConfig,
+    pub pipeline: PipelineConfig,
+    pub robots: RobotsPolicy,
+}
+
+impl Config {
+    /// Load the configuration file, the default configuration is used if there is no file
+    pub fn load() -> Result<Self, ConfigError> {
+        match std::env::var("OPEN_FINDER_CONFIG") {
+            Ok(path) => Config::load_from(path),
+            Err(_) if Path::new(CONFIG_PATH).exists() => Config::load_from(CONFIG_PATH),
+            Err(_) => Ok(Config::default()),
+        }
+    }
+
+    pub fn load_from(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Config::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, ConfigError> {
+        toml::from_str(content).map_err(ConfigError::Toml)
+    }
+}
+
+mod errors {
+    use std::{
+        fmt::{Display, Formatter},
+        path::PathBuf,
+    };
+
+    #[derive(Debug)]
+    pub enum ConfigError {
+        Io(std::io::Error),
+        /// A root certificate of the client profile can't be read
+        Certificate(PathBuf, std::io::Error),
+        Toml(toml::de::Error),
+        Regex(regex::Error),
+        Client(reqwest::Error),
+        InvalidHeader(String),
+    }
+
+    impl Display for ConfigError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                ConfigError::Io(err) => write!(f, "can't read the configuration: {}", err),
+                ConfigError::Certificate(path, err) => {
+                    write!(f, "can't read the root certificate {}: {}", path.display(), err)
+                }
+                ConfigError::Toml(err) => write!(f, "invalid configuration: {}", err),
+                ConfigError::Regex(err) => write!(f, "invalid regex: {}", err),
+                ConfigError::Client(err) => write!(f, "invalid client profile: {}", err),
+                ConfigError::InvalidHeader(name) => write!(f, "invalid header: {}", name),
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::link::{Link, LinkSource, Url};
+
+/// Remove the comments of a stylesheet
+fn remove_comments(content: &str) -> String {
+    let mut output = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("/*") {
+        output.push_str(&rest[..start]);
+        rest = match rest[start + 2..].find("*/") {
+            Some(end) => &rest[start + 2 + end + 2..],
+            None => "",
+        };
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Read a quoted string at the start of the input, `\` escapes the next character.
+/// Return the value and the rest of the input.
+fn read_string(input: &str) -> Option<(String, &str)> {
+    let quote = input.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let mut value = String::new();
+    let mut chars = input.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some((_, escaped)) = chars.next() {
+                    value.push(escaped);
+                }
+            }
+            c if c == quote => return Some((value, &input[i + 1..])),
+            '\n' => return None,
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Read the value of a `url(...)` whose `url(` is already read
+fn read_url(input: &str) -> Option<String> {
+    let input = input.trim_start();
+    if let Some((value, rest)) = read_string(input) {
+        return rest.trim_start().starts_with(')').then_some(value);
+    }
+    let end = input.find(')')?;
+    Some(input[..end].trim().to_string())
+}
+
+/// Find the urls of a stylesheet with the rule that contains them: `url`, `@import`
+/// or `image-set`
+pub fn get_urls(content: &str) -> Vec<(&'static str, String)> {
+    let content = remove_comments(content);
+    let lower = content.to_ascii_lowercase();
+    let mut urls = Vec::new();
+    let mut i = 0;
+    while i < content.len() {
+        let rest = &lower[i..];
+        let next = ["url(", "@import", "image-set("]
+            .iter()
+            .filter_map(|keyword| Some((rest.find(keyword)?, *keyword)))
+            .min();
+        let Some((j, keyword)) = next else {
+            break;
+        };
+        let start = i + j + keyword.len();
+        let after = &content[start..];
+        match keyword {
+            "url(" => {
+                // `url(` preceded by a letter is another function, like `myurl(`
+                let is_function = lower[..i + j].ends_with(|c: char| c.is_ascii_alphanumeric() || c == '-');
+                if let Some(url) = read_url(after).filter(|_| !is_function) {
+                    urls.push(("url", url));
+                }
+            }
+            "@import" => {
+                // `@import "style.css";`, the `url()` form is read as an url
+                if let Some((url, _)) = read_string(after.trim_start()) {
+                    urls.push(("@import", url));
+                }
+            }
+            _ => {
+                // `image-set("a.png" 1x, url(b.png) 2x)`, the strings of the set are
+                // urls but not the ones in functions like `type("image/avif")`
+                let mut depth = 1;
+                let mut set = after;
+                while let Some(k) = set.find(['"', '\'', '(', ')']) {
+                    match set.as_bytes()[k] {
+                        b'(' => depth += 1,
+                        b')' => depth -= 1,
+                        _ => {
+                            let Some((url, rest)) = read_string(&set[k..]) else {
+                                break;
+                            };
+                            if depth == 1 {
+                                urls.push(("image-set", url));
+                            }
+                            set = rest;
+                            continue;
+                        }
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                    set = &set[k + 1..];
+                }
+            }
+        }
+        i = start;
+    }
+    urls.retain(|(_, url)| !url.is_empty());
+    urls
+}
+
+/// Parse the links of a stylesheet, resolved against the url of the stylesheet
+pub fn get_links(content: &str, url: &Url) -> Vec<Link> {
+    get_urls(content)
+        .into_iter()
+        .filter_map(|(rule, value)| {
+            let target = url.join(&value).ok()?;
+            Some((target, rule))
+        })
+        .enumerate()
+        .map(|(position, (target, rule))| {
+            Link::new(
+                target,
+                LinkSource::Css {
+                    rule: rule.to_string(),
+                },
+                position,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_css_links() {
+        let stylesheet = Url::parse("https://www.example.com/css/main.css").unwrap();
+        let content = r#"
+            @import "reset.css";
+            @import url('/fonts/fonts.css') screen;
+            /* background: url(commented.png); */
+            body { background: URL( "../img/bg.png" ) no-repeat; }
+            .logo { background-image: url(logo.svg); }
+            .hero {
+                background-image: -webkit-image-set("hero.png" 1x, 'hero@2x.png' 2x);
+                background-image: image-set(url(hero.avif) type("image/avif"), "hero.jpg" 1x);
+            }
+            @font-face { src: url("/fonts/a.woff2?v=1#iefix") format("woff2"), url(data:font/woff;base64,AAAA); }
+            .escaped { background: url('it\'s.png'); }
+            .other { content: myurl(not-a-link.png); }
+        "#;
+        let links: Vec<(String, String)> = get_links(content, &stylesheet)
+            .into_iter()
+            .map(|link| match link.source {
+                LinkSource::Css { rule } => (rule, link.url.to_string()),
+                _ => panic!("not a css link"),
+            })
+            .collect();
+        let expected = [
+            ("@import", "https://www.example.com/css/reset.css"),
+            ("url", "https://www.example.com/fonts/fonts.css"),
+            ("url", "https://www.example.com/img/bg.png"),
+            ("url", "https://www.example.com/css/logo.svg"),
+            ("image-set", "https://www.example.com/css/hero.png"),
+            ("image-set", "https://www.example.com/css/hero@2x.png"),
+            ("image-set", "https://www.example.com/css/hero.jpg"),
+            ("url", "https://www.example.com/css/hero.avif"),
+            ("url", "https://www.example.com/fonts/a.woff2?v=1#iefix"),
+            ("url", "https://www.example.com/css/it's.png"),
+        ];
+        let expected: Vec<(String, String)> = expected
+            .iter()
+            .map(|(rule, url)| (rule.to_string(), url.to_string()))
+            .collect();
+        assert_eq!(links, expected);
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use reqwest::{
+    header::{CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE},
+    Response, StatusCode,
+};
+use serde::Deserialize;
+
+use crate::{client::HttpClient, content::ContentType, link::Url};
+
+/// Request sent before the download to read the length and the type of a body
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preflight {
+    /// The length is read from the headers of the response
+    #[default]
+    None,
+    /// A `HEAD` request
+    Head,
+    /// A `GET` of the first byte, for the servers that do not answer to `HEAD`
+    Range,
+}
+
+/// Limits of the downloaded bodies
+///
+/// ```toml
+/// [download]
+/// max_size = 10485760
+/// preflight = "head"
+///
+/// [download.limits]
+/// pdf = 52428800
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloadConfig {
+    /// Maximum size of a body, in bytes
+    pub max_size: u64,
+    /// Maximum size by content type (`html`, `pdf`, `image`...), in bytes
+    pub limits: HashMap<String, u64>,
+    pub preflight: Preflight,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        DownloadConfig {
+            max_size: 10 * 1024 * 1024,
+            limits: HashMap::from([(String::from("pdf"), 50 * 1024 * 1024)]),
+            preflight: Preflight::None,
+        }
+    }
+}
+
+impl DownloadConfig {
+    /// Get the maximum size of a content type
+    pub fn max_size_of(&self, kind: &ContentType) -> u64 {
+        self.limits.get(kind.name()).copied().unwrap_or(self.max_size)
+    }
+}
+
+/// Why the body of a page is incomplete
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Truncation {
+    /// The announced length is over the limit, the body is not downloaded
+    Skipped,
+    /// The body is cut at the limit
+    Truncated,
+}
+
+impl std::fmt::Display for Truncation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Truncation::Skipped => write!(f, "skipped"),
+            Truncation::Truncated => write!(f, "truncated"),
+        }
+    }
+}
+
+/// What the headers of a response say about its body
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Head {
+    pub status: u16,
+    /// The `Content-Type` header
+    pub mime: Option<String>,
+    /// The announced length of the whole body
+    pub length: Option<u64>,
+}
+
+impl Head {
+    pub fn of(res: &Response) -> Self {
+        let header = |name| res.headers().get(name).and_then(|value| value.to_str().ok());
+        // The total of a `Content-Range: bytes 0-0/1234` is the length of the whole body,
+        // the `Content-Length` of a partial response is only the length of the range
+        let length = match res.status() == StatusCode::PARTIAL_CONTENT {
+            true => header(CONTENT_RANGE).and_then(|range| range.rsplit_once('/')?.1.trim().parse().ok()),
+            false => header(CONTENT_LENGTH).and_then(|length| length.trim().parse().ok()),
+        };
+        Head {
+            status: res.status().as_u16(),
+            mime: header(CONTENT_TYPE).map(str::to_string),
+            length,
+        }
+    }
+}
+
+/// Read the headers of an url without its body, `None` without preflight or if
+/// the request fails
+pub async fn preflight(url: &Url, preflight: Preflight, client: &HttpClient) -> Option<Head> {
+    let request = match preflight {
+        Preflight::None => return None,
+        Preflight::Head => client.head(url),
+        Preflight::Range => client.get(url).header(RANGE, "bytes=0-0"),
+    };
+    let res = request.send().await.ok()?;
+    Some(Head::of(&res))
+}
+
+/// Download a body as it arrives, it is cut at the limit given by its first chunk
+/// to sniff its type. Return the bytes and whether they are truncated.
+pub async fn read_body(mut res: Response, limit: impl Fn(&[u8]) -> u64) -> Result<(Vec<u8>, bool), reqwest::Error> {
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut max_size = None;
+    while let Some(chunk) = res.chunk().await? {
+        let max_size = *max_size.get_or_insert_with(|| limit(&chunk)) as usize;
+        let remaining = max_size.saturating_sub(bytes.len());
+        if chunk.len() > remaining {
+            bytes.extend_from_slice(&chunk[..remaining]);
+            return Ok((bytes, true));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok((bytes, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, test_server};
+
+    #[test]
+    fn test_download_config() {
+        let config = Config::parse(
+            r#"
+            [download]
+            max_size = 1000
+            preflight = "range"
+
+            [download.limits]
+            html = 500
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.download.preflight, Preflight::Range);
+        assert_eq!(config.download.max_size_of(&ContentType::Html), 500);
+        assert_eq!(config.download.max_size_of(&ContentType::Pdf), 1000);
+
+        let default = DownloadConfig::default();
+        assert_eq!(default.max_size_of(&ContentType::Pdf), 50 * 1024 * 1024);
+        assert_eq!(default.max_size_of(&ContentType::Other), 10 * 1024 * 1024);
+        assert!(Config::parse("[download]\npreflight = \"options\"").is_err());
+    }
+
+    /// Serve a response on a local port
+    async fn serve(response: &'static str) -> Url {
+        Url::parse(format!("http://{}/download.php", test_server::serve(response).await)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_read_body() {
+        let client = HttpClient::default();
+        let url = serve("HTTP/1.1 200 OK\r\nContent-Type: video/mp4\r\nContent-Length: 26\r\n\r\nabcdefghijklmnopqrstuvwxyz").await;
+        let res = client.get(&url).send().await.unwrap();
+        let head = Head::of(&res);
+        assert_eq!(head.mime.as_deref(), Some("video/mp4"));
+        assert_eq!(head.length, Some(26));
+        let (bytes, truncated) = read_body(res, |start| if start.starts_with(b"abc") { 10 } else { 0 }).await.unwrap();
+        assert_eq!(bytes, b"abcdefghij");
+        assert!(truncated);
+
+        let url = serve("HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-0/123456\r\nContent-Length: 1\r\n\r\na").await;
+        let head = preflight(&url, Preflight::Range, &client).await.unwrap();
+        assert_eq!(head, Head { status: 206, mime: None, length: Some(123_456) });
+
+        // The total is unknown
+        let url = serve("HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-0/*\r\nContent-Length: 1\r\n\r\na").await;
+        let head = preflight(&url, Preflight::Range, &client).await.unwrap();
+        assert_eq!(head.length, None);
+    }
+}
@@ -0,0 +1,132 @@
+use console::style;
+
+use crate::{collection::UrlCollection, link::Url};
+
+/// What the crawler would do with an url of the input
+#[derive(Debug)]
+pub struct Explanation {
+    /// The url as written in the input
+    pub input: String,
+    /// The url once canonicalized, `None` if it is not a valid url
+    pub url: Option<Url>,
+    pub accepted: bool,
+    /// The rule or the check responsible
+    pub reason: String,
+}
+
+/// Get the urls of a list with an url by line, of a `to_fetch.csv` or of a
+/// `fetcheds.csv` (`status;url`)
+pub fn read_urls(content: &str) -> Vec<&str> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter(|line| *line != "url" && *line != "status;label")
+        .map(|line| match line.split_once(';') {
+            Some((status, url)) if status.chars().all(|c| c.is_ascii_digit()) => url,
+            _ => line,
+        })
+        .collect()
+}
+
+/// Run the urls through the checks of the fetch without any request, in order:
+/// validity, canonicalization, duplicates, traps, medias, scope and cached robots.txt
+pub fn explain(collection: &mut UrlCollection, content: &str) -> Vec<Explanation> {
+    read_urls(content)
+        .into_iter()
+        .map(|input| match Url::parse(input) {
+            Ok(url) => {
+                let url = collection.canonicalize(&url);
+                let verdict = collection.check_new(&url);
+                Explanation {
+                    input: input.to_string(),
+                    accepted: verdict.is_accepted(),
+                    reason: verdict.to_string(),
+                    url: Some(url),
+                }
+            }
+            Err(err) => Explanation {
+                input: input.to_string(),
+                url: None,
+                accepted: false,
+                reason: format!("invalid url ({})", err),
+            },
+        })
+        .collect()
+}
+
+/// Print what the crawler would do with the urls
+pub fn run(collection: &mut UrlCollection, content: &str) {
+    let explanations = explain(collection, content);
+    for explanation in explanations.iter() {
+        let url = explanation
+            .url
+            .as_ref()
+            .map(|url| url.to_string())
+            .unwrap_or_else(|| explanation.input.clone());
+        let verdict = if explanation.accepted {
+            style("accept").green()
+        } else {
+            style("reject").red()
+        };
+        println!("{} {} ({})", verdict, url, explanation.reason);
+    }
+    let accepted = explanations.iter().filter(|explanation| explanation.accepted).count();
+    println!(
+        "{} accepted, {} rejected",
+        accepted,
+        explanations.len() - accepted
+    );
+    for trapped in collection.get_trapped() {
+        println!(
+            "{} {} ({}, {} urls rejected)",
+            style("trap").yellow(),
+            trapped.pattern,
+            trapped.trap,
+            trapped.rejected
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_urls() {
+        let to_fetch = "url\nhttps://www.insa-rouen.fr/\n\nhttps://moodle.insa-rouen.fr/\n";
+        assert_eq!(read_urls(to_fetch), vec!["https://www.insa-rouen.fr/", "https://moodle.insa-rouen.fr/"]);
+        let fetcheds = "status;label\n200;https://www.insa-rouen.fr/\n0;https://a.com/a.png\n";
+        assert_eq!(read_urls(fetcheds), vec!["https://www.insa-rouen.fr/", "https://a.com/a.png"]);
+        let list = "# seeds\nhttps://www.insa-rouen.fr/a;b\n";
+        assert_eq!(read_urls(list), vec!["https://www.insa-rouen.fr/a;b"]);
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut collection = UrlCollection::new();
+        let content = "https://WWW.insa-rouen.fr:443/a/../b#top\n\
+            https://www.insa-rouen.fr/b?utm_source=mail\n\
+            https://www.insa-rouen.fr/b\n\
+            https://www.insa-rouen.fr/logo.png\n\
+            https://qualite.insa-rouen.fr/\n\
+            https://www.google.com/\n\
+            www.insa-rouen.fr\n";
+        let explanations: Vec<(bool, Option<String>, String)> = explain(&mut collection, content)
+            .into_iter()
+            .map(|e| (e.accepted, e.url.map(|url| url.to_string()), e.reason))
+            .collect();
+        assert_eq!(
+            explanations,
+            vec![
+                (true, Some(String::from("https://www.insa-rouen.fr/b")), String::from("include by rule #5 (insa-rouen.fr)")),
+                (false, Some(String::from("https://www.insa-rouen.fr/b")), String::from("already known")),
+                (false, Some(String::from("https://www.insa-rouen.fr/b")), String::from("already known")),
+                (false, Some(String::from("https://www.insa-rouen.fr/logo.png")), String::from("media")),
+                (false, Some(String::from("https://qualite.insa-rouen.fr/")), String::from("exclude by rule #4 (qualite)")),
+                (false, Some(String::from("https://www.google.com/")), String::from("exclude by default")),
+                (false, None, String::from("invalid url (NoProtocol)")),
+            ]
+        );
+    }
+}
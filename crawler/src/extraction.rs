@@ -0,0 +1,144 @@
+use std::{
+    fmt::Display,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Semaphore;
+
+use crate::content::Content;
+
+/// Counters of the extraction pool
+#[derive(Debug, Default)]
+struct Metrics {
+    queued: AtomicUsize,
+    running: AtomicUsize,
+    done: AtomicUsize,
+    /// Time spent extracting, in microseconds
+    busy: AtomicU64,
+}
+
+/// Counts a task in a counter while it is alive
+struct Gauge<'a>(&'a AtomicUsize);
+
+impl<'a> Gauge<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Gauge(counter)
+    }
+}
+
+impl Drop for Gauge<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A snapshot of the extraction pool
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExtractionStats {
+    /// Contents waiting for a worker
+    pub queued: usize,
+    pub running: usize,
+    pub done: usize,
+    /// Time spent extracting by all the workers
+    pub busy: Duration,
+}
+
+impl Display for ExtractionStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} queued, {} running, {} done in {:.0?}",
+            self.queued, self.running, self.done, self.busy
+        )
+    }
+}
+
+/// Extracts the texts of the contents on the blocking threads, with at most
+/// `workers` extractions at the same time, so that a large PDF does not hold
+/// a thread of the network tasks. The clones share the workers.
+#[derive(Clone)]
+pub struct ExtractionPool {
+    workers: Arc<Semaphore>,
+    metrics: Arc<Metrics>,
+}
+
+impl ExtractionPool {
+    pub fn new(workers: usize) -> Self {
+        ExtractionPool {
+            workers: Arc::new(Semaphore::new(workers.max(1))),
+            metrics: Arc::default(),
+        }
+    }
+
+    /// Extract the text of the content once a worker is free, the text is kept in
+    /// the content
+    pub async fn extract(&self, content: Arc<Content>) {
+        if !content.needs_extraction() {
+            return;
+        }
+        let queued = Gauge::new(&self.metrics.queued);
+        // The semaphore is never closed
+        let Ok(_worker) = self.workers.acquire().await else {
+            return;
+        };
+        drop(queued);
+
+        let _running = Gauge::new(&self.metrics.running);
+        let started = Instant::now();
+        // A panic of the extraction only loses the text of the content, it is not
+        // extracted again
+        let extracted = tokio::task::spawn_blocking({
+            let content = Arc::clone(&content);
+            move || content.to_text()
+        });
+        if extracted.await.is_err() {
+            content.set_no_text();
+        }
+        self.metrics.busy.fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+        self.metrics.done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> ExtractionStats {
+        ExtractionStats {
+            queued: self.metrics.queued.load(Ordering::Relaxed),
+            running: self.metrics.running.load(Ordering::Relaxed),
+            done: self.metrics.done.load(Ordering::Relaxed),
+            busy: Duration::from_micros(self.metrics.busy.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_extraction_pool() {
+        let pool = ExtractionPool::new(1);
+        let content = |text: &str| Arc::new(Content::new(text.as_bytes().to_vec(), String::from("page.html"), None));
+        let (first, second) = (content("<p>First page</p>"), content("<p>Second page</p>"));
+        tokio::join!(pool.extract(Arc::clone(&first)), pool.extract(Arc::clone(&second)));
+        assert!(first.to_text().unwrap().contains("First page"));
+        assert!(!second.needs_extraction());
+
+        // The text is extracted once
+        pool.extract(first).await;
+        let stats = pool.stats();
+        assert_eq!((stats.queued, stats.running, stats.done), (0, 0, 2));
+
+        let image = Arc::new(Content::new(b"\x89PNG\r\n\x1a\n".to_vec(), String::from("a.png"), None));
+        pool.extract(image).await;
+        assert_eq!(pool.stats().done, 2);
+
+        // A failed extraction is not run again
+        let failed = content("<p>Failed page</p>");
+        failed.set_no_text();
+        assert!(!failed.needs_extraction());
+        assert_eq!(failed.get_text(), None);
+    }
+}
@@ -0,0 +1,241 @@
+use quick_xml::{events::Event, Reader};
+use serde::{Deserialize, Serialize};
+
+use crate::link::Url;
+
+/// Maximum length of a summary, in characters
+const MAX_SUMMARY_LENGTH: usize = 1_000;
+
+/// What a feed says about a page, attached to its document
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedEntry {
+    pub title: Option<String>,
+    /// The description or the summary, without its html
+    pub summary: Option<String>,
+    /// Update date, or else publication date, as written in the feed
+    pub date: Option<String>,
+}
+
+/// An item of a RSS feed or an entry of an Atom feed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeedItem {
+    pub url: Url,
+    pub entry: FeedEntry,
+}
+
+/// Remove the tags of an html summary and collapse its whitespaces
+fn strip_html(summary: &str) -> String {
+    let mut text = String::with_capacity(summary.len());
+    let mut in_tag = false;
+    for c in summary.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => (),
+        }
+    }
+    let text = html_escape::decode_html_entities(&text);
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(MAX_SUMMARY_LENGTH)
+        .collect()
+}
+
+/// Whether the content is a RSS or Atom feed
+pub fn is_feed(content: &str) -> bool {
+    let mut reader = Reader::from_str(content);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => {
+                return matches!(e.local_name().as_ref(), b"rss" | b"feed" | b"RDF");
+            }
+            Ok(Event::Eof) | Err(_) => return false,
+            _ => (),
+        }
+    }
+}
+
+/// Parse the items of a RSS (2.0 or 1.0) or Atom feed, `None` if the content is not a feed
+///
+/// The links are resolved against the url of the feed.
+pub fn parse(content: &str, url: &Url) -> Option<Vec<FeedItem>> {
+    if !is_feed(content) {
+        return None;
+    }
+    let mut reader = Reader::from_str(content);
+    reader.config_mut().trim_text(true);
+
+    let mut items = Vec::new();
+    // The `<item>` or `<entry>` we are in: (link, entry)
+    let mut item: Option<(Option<String>, FeedEntry)> = None;
+    let mut field: Vec<u8> = Vec::new();
+    loop {
+        let text = match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                match e.local_name().as_ref() {
+                    b"item" | b"entry" => item = Some((None, FeedEntry::default())),
+                    b"link" => {
+                        // Atom: <link rel="alternate" href="..."/>, the other `rel` are not the page
+                        if let Some((link, _)) = item.as_mut() {
+                            if let Some(href) = atom_link(&e) {
+                                link.get_or_insert(href);
+                            }
+                        }
+                        field = b"link".to_vec();
+                    }
+                    name => field = name.to_vec(),
+                }
+                continue;
+            }
+            Ok(Event::Empty(e)) => {
+                if let (b"link", Some((link, _))) = (e.local_name().as_ref(), item.as_mut()) {
+                    if let Some(href) = atom_link(&e) {
+                        link.get_or_insert(href);
+                    }
+                }
+                continue;
+            }
+            Ok(Event::End(e)) => {
+                if matches!(e.local_name().as_ref(), b"item" | b"entry") {
+                    if let Some((Some(link), entry)) = item.take() {
+                        if let Ok(url) = url.join(link.trim()) {
+                            items.push(FeedItem { url, entry });
+                        }
+                    }
+                }
+                field.clear();
+                continue;
+            }
+            Ok(Event::Text(text)) => text.unescape().map(|text| text.into_owned()).unwrap_or_default(),
+            Ok(Event::CData(text)) => String::from_utf8_lossy(&text).into_owned(),
+            Ok(Event::Eof) | Err(_) => break,
+            _ => continue,
+        };
+
+        let Some((link, entry)) = item.as_mut() else {
+            continue;
+        };
+        let text = text.trim();
+        match field.as_slice() {
+            b"title" => entry.title = Some(strip_html(text)),
+            b"link" => {
+                link.get_or_insert(text.to_string());
+            }
+            // A permalink guid is the url of the item when there is no link
+            b"guid" if text.starts_with("http") => {
+                link.get_or_insert(text.to_string());
+            }
+            b"description" | b"summary" | b"content" | b"encoded" if entry.summary.is_none() => {
+                entry.summary = Some(strip_html(text)).filter(|summary| !summary.is_empty());
+            }
+            // The update date wins, a changed item is fetched again
+            b"updated" => entry.date = Some(text.to_string()),
+            b"pubDate" | b"published" | b"date" if entry.date.is_none() => {
+                entry.date = Some(text.to_string());
+            }
+            _ => (),
+        }
+    }
+    Some(items)
+}
+
+/// Get the `href` of an Atom `<link>` to the page of the entry
+fn atom_link(e: &quick_xml::events::BytesStart) -> Option<String> {
+    let attribute = |name: &[u8]| {
+        e.attributes()
+            .flatten()
+            .find(|attr| attr.key.as_ref() == name)
+            .and_then(|attr| attr.unescape_value().ok())
+            .map(|value| value.into_owned())
+    };
+    let rel = attribute(b"rel");
+    if rel.as_deref().is_some_and(|rel| rel != "alternate") {
+        return None;
+    }
+    attribute(b"href")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rss() {
+        let feed = Url::parse("https://www.example.com/news/feed.xml").unwrap();
+        let content = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/">
+            <channel>
+                <title>News</title>
+                <link>https://www.example.com/news/</link>
+                <item>
+                    <title>Open days &amp; visits</title>
+                    <link>/news/open-days.html</link>
+                    <description><![CDATA[<p>Come and <b>visit</b>  the campus</p>]]></description>
+                    <pubDate>Mon, 02 Sep 2024 10:00:00 +0200</pubDate>
+                </item>
+                <item>
+                    <title>No link</title>
+                    <guid isPermaLink="true">https://www.example.com/news/guid.html</guid>
+                </item>
+                <item><title>Nothing</title></item>
+            </channel>
+            </rss>"#;
+        assert_eq!(
+            parse(content, &feed).unwrap(),
+            vec![
+                FeedItem {
+                    url: Url::parse("https://www.example.com/news/open-days.html").unwrap(),
+                    entry: FeedEntry {
+                        title: Some(String::from("Open days & visits")),
+                        summary: Some(String::from("Come and visit the campus")),
+                        date: Some(String::from("Mon, 02 Sep 2024 10:00:00 +0200")),
+                    },
+                },
+                FeedItem {
+                    url: Url::parse("https://www.example.com/news/guid.html").unwrap(),
+                    entry: FeedEntry {
+                        title: Some(String::from("No link")),
+                        ..FeedEntry::default()
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_atom() {
+        let feed = Url::parse("https://www.example.com/atom").unwrap();
+        let content = r#"<feed xmlns="http://www.w3.org/2005/Atom">
+            <title>Announcements</title>
+            <link href="https://www.example.com/"/>
+            <entry>
+                <title type="html">Exams &lt;b&gt;planning&lt;/b&gt;</title>
+                <link rel="edit" href="/edit/1"/>
+                <link href="/announcements/exams"/>
+                <published>2024-08-30T08:00:00Z</published>
+                <updated>2024-09-01T12:00:00Z</updated>
+                <summary>The planning is online</summary>
+            </entry>
+        </feed>"#;
+        assert_eq!(
+            parse(content, &feed).unwrap(),
+            vec![FeedItem {
+                url: Url::parse("https://www.example.com/announcements/exams").unwrap(),
+                entry: FeedEntry {
+                    title: Some(String::from("Exams planning")),
+                    summary: Some(String::from("The planning is online")),
+                    date: Some(String::from("2024-09-01T12:00:00Z")),
+                },
+            }]
+        );
+
+        assert!(parse("<urlset><url><loc>https://a.fr/</loc></url></urlset>", &feed).is_none());
+        assert!(parse("<!DOCTYPE html><html></html>", &feed).is_none());
+    }
+}
@@ -0,0 +1,552 @@
+use html5ever::tendril::StrTendril;
+use html5ever::tokenizer::{
+    states::RawKind, BufferQueue, Tag, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer,
+    TokenizerOpts,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    css, js,
+    link::{Link, LinkSource, Rel, Url},
+    robots::Directives,
+};
+
+/// Maximum length of the text of a link in bytes, the text of an unclosed `<a>`
+/// would be the rest of the document
+const MAX_LINK_TEXT: usize = 1024;
+
+/// Attributes containing a single url, by element
+const URL_ATTRIBUTES: [(&str, &str); 21] = [
+    ("a", "href"),
+    ("area", "href"),
+    ("link", "href"),
+    ("img", "src"),
+    ("script", "src"),
+    ("iframe", "src"),
+    ("frame", "src"),
+    ("embed", "src"),
+    ("source", "src"),
+    ("track", "src"),
+    ("audio", "src"),
+    ("video", "src"),
+    ("video", "poster"),
+    ("input", "src"),
+    ("object", "data"),
+    ("form", "action"),
+    ("button", "formaction"),
+    ("input", "formaction"),
+    ("blockquote", "cite"),
+    ("q", "cite"),
+    ("ins", "cite"),
+];
+
+/// A link before its resolution
+struct RawLink {
+    value: String,
+    source: LinkSource,
+    text: String,
+    title: Option<String>,
+    rel: Rel,
+    hreflang: Option<String>,
+}
+
+/// Collect the links of the tokens, the links are resolved at the end
+/// because the `<base>` of the document applies to the links before it
+#[derive(Default)]
+struct LinkSink {
+    base: Option<String>,
+    links: Vec<RawLink>,
+    /// Index of the link of the `<a>` we are in
+    anchor: Option<usize>,
+    /// Content of the `<style>` we are in
+    style: Option<String>,
+    /// Content of the inline `<script>` we are in
+    script: Option<String>,
+    robots: Directives,
+}
+
+/// A version of the document in another language, `<link rel="alternate" hreflang="en">`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Alternate {
+    /// Language code, or `x-default` for the version chosen when no language matches
+    pub lang: String,
+    pub url: Url,
+}
+
+/// What the crawler reads in an html document
+pub struct HtmlDocument {
+    pub links: Vec<Link>,
+    /// Directives of the `<meta name="robots">` tags
+    pub robots: Directives,
+    /// The `<link rel="canonical">` of the document
+    pub canonical: Option<Url>,
+    pub alternates: Vec<Alternate>,
+}
+
+impl LinkSink {
+    fn add(&mut self, value: &str, element: &str, attribute: &str, tag: &Tag) {
+        let get_attribute = |name: &str| {
+            tag.attrs
+                .iter()
+                .find(|attr| &*attr.name.local == name)
+                .map(|attr| attr.value.to_string())
+        };
+        self.links.push(RawLink {
+            value: value.to_string(),
+            source: LinkSource::Html {
+                element: element.to_string(),
+                attribute: attribute.to_string(),
+            },
+            text: get_attribute("alt").unwrap_or_default(),
+            title: get_attribute("title"),
+            rel: get_attribute("rel").map(|rel| Rel::parse(&rel)).unwrap_or_default(),
+            hreflang: get_attribute("hreflang"),
+        });
+    }
+
+    /// Add the urls of a stylesheet, from a `<style>` or a `style` attribute
+    fn add_css(&mut self, content: &str) {
+        for (rule, value) in css::get_urls(content) {
+            self.links.push(RawLink {
+                value,
+                source: LinkSource::Css {
+                    rule: rule.to_string(),
+                },
+                text: String::new(),
+                title: None,
+                rel: Rel::default(),
+                hreflang: None,
+            });
+        }
+    }
+
+    /// Add the string literals of a script that look like urls
+    fn add_script(&mut self, content: &str) {
+        for value in js::get_urls(content) {
+            self.links.push(RawLink {
+                value,
+                source: LinkSource::Script,
+                text: String::new(),
+                title: None,
+                rel: Rel::default(),
+                hreflang: None,
+            });
+        }
+    }
+
+    fn process_tag(&mut self, tag: &Tag) {
+        let element = &*tag.name;
+        let get_attribute = |name: &str| {
+            tag.attrs
+                .iter()
+                .find(|attr| &*attr.name.local == name)
+                .map(|attr| &*attr.value)
+        };
+
+        if tag.kind == TagKind::EndTag {
+            match element {
+                "a" => self.anchor = None,
+                "style" => {
+                    if let Some(style) = self.style.take() {
+                        self.add_css(&style);
+                    }
+                }
+                "script" => {
+                    if let Some(script) = self.script.take() {
+                        self.add_script(&script);
+                    }
+                }
+                _ => (),
+            }
+            return;
+        }
+
+        // The alternative text of the images of a link is part of its text
+        if let (Some(anchor), Some(alt)) = (self.anchor, get_attribute("alt")) {
+            let text = &mut self.links[anchor].text;
+            push_text(text, " ");
+            push_text(text, alt);
+        }
+
+        for attr in tag.attrs.iter() {
+            let attribute = &*attr.name.local;
+            let javascript = attr.value.get(..11).filter(|scheme| scheme.eq_ignore_ascii_case("javascript:"));
+            if attribute.starts_with("on") {
+                // onclick="location.href='/page.html'"
+                self.add_script(&attr.value);
+            } else if javascript.is_some() && URL_ATTRIBUTES.contains(&(element, attribute)) {
+                // href="javascript:window.open('/page.html')"
+                self.add_script(&attr.value[11..]);
+            } else if URL_ATTRIBUTES.contains(&(element, attribute)) {
+                self.add(&attr.value, element, attribute, tag);
+                if element == "a" {
+                    self.anchor = Some(self.links.len() - 1);
+                }
+            } else if attribute == "style" {
+                self.add_css(&attr.value);
+            } else if attribute == "srcset" && matches!(element, "img" | "source") {
+                // srcset="image-1x.png 1x, image-2x.png 2x"
+                for candidate in attr.value.split(',') {
+                    if let Some(value) = candidate.split_whitespace().next() {
+                        self.add(value, element, attribute, tag);
+                    }
+                }
+            }
+        }
+
+        match element {
+            "style" => self.style = Some(String::new()),
+            // Inline scripts, `<script type="text/template">` is not a script
+            "script" if get_attribute("src").is_none() => {
+                let is_script = get_attribute("type").is_none_or(|kind| {
+                    let kind = kind.to_ascii_lowercase();
+                    kind.is_empty() || kind.contains("javascript") || kind.contains("module") || kind.contains("json")
+                });
+                if is_script {
+                    self.script = Some(String::new());
+                }
+            }
+            "base" if self.base.is_none() => {
+                self.base = get_attribute("href").map(str::to_string);
+            }
+            "meta" => {
+                // <meta http-equiv="refresh" content="5; url=https://example.com">
+                let is_refresh = get_attribute("http-equiv")
+                    .is_some_and(|value| value.eq_ignore_ascii_case("refresh"));
+                if let Some(content) = get_attribute("content").filter(|_| is_refresh) {
+                    if let Some(value) = refresh_url(content) {
+                        self.add(value, element, "content", tag);
+                    }
+                }
+                if let (Some(name), Some(content)) = (get_attribute("name"), get_attribute("content")) {
+                    self.robots = self.robots.merge(Directives::parse_meta(name, content));
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl TokenSink for LinkSink {
+    type Handle = ();
+
+    fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::TagToken(tag) => {
+                self.process_tag(&tag);
+                if tag.kind == TagKind::StartTag {
+                    // The content of these elements is not html
+                    return match &*tag.name {
+                        "script" => TokenSinkResult::RawData(RawKind::ScriptData),
+                        "style" | "xmp" | "iframe" | "noembed" | "noframes" => {
+                            TokenSinkResult::RawData(RawKind::Rawtext)
+                        }
+                        "title" | "textarea" => TokenSinkResult::RawData(RawKind::Rcdata),
+                        _ => TokenSinkResult::Continue,
+                    };
+                }
+            }
+            Token::CharacterTokens(text) => {
+                if let Some(style) = self.style.as_mut() {
+                    style.push_str(&text);
+                } else if let Some(script) = self.script.as_mut() {
+                    script.push_str(&text);
+                } else if let Some(anchor) = self.anchor {
+                    push_text(&mut self.links[anchor].text, &text);
+                }
+            }
+            _ => (),
+        }
+        TokenSinkResult::Continue
+    }
+}
+
+/// Add to the text of a link up to `MAX_LINK_TEXT`
+fn push_text(text: &mut String, value: &str) {
+    let mut end = MAX_LINK_TEXT.saturating_sub(text.len()).min(value.len());
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.push_str(&value[..end]);
+}
+
+/// Get the url of the `content` of a `<meta http-equiv="refresh">`
+fn refresh_url(content: &str) -> Option<&str> {
+    let (_, url) = content.split_once([';', ','])?;
+    let url = url.trim_start();
+    if !url.get(..3)?.eq_ignore_ascii_case("url") {
+        return None;
+    }
+    let url = url[3..].trim_start().strip_prefix('=')?.trim();
+    Some(url.trim_matches(|c| c == '"' || c == '\''))
+}
+
+/// Parse all the links of an html document with their element, attribute, text and `rel`
+pub fn get_links(content: &str, url: &Url) -> Vec<Link> {
+    parse(content, url).links
+}
+
+/// Parse an html document
+///
+/// The html entities are decoded and the links are resolved against the base of the
+/// document: the `href` of the first `<base>` or the url of the document.
+pub fn parse(content: &str, url: &Url) -> HtmlDocument {
+    let mut tokenizer = Tokenizer::new(LinkSink::default(), TokenizerOpts::default());
+    let mut input = BufferQueue::default();
+    input.push_back(StrTendril::from(content));
+    let _ = tokenizer.feed(&mut input);
+    tokenizer.end();
+    let sink = tokenizer.sink;
+
+    let base = sink
+        .base
+        .and_then(|base| url.join(&base).ok())
+        .unwrap_or_else(|| url.clone());
+
+    let mut canonical = None;
+    let mut alternates = Vec::new();
+    let links = sink
+        .links
+        .into_iter()
+        .filter_map(|link| Some((base.join(&link.value).ok()?, link)))
+        .enumerate()
+        .map(|(position, (url, link))| {
+            let is_link_element = matches!(&link.source, LinkSource::Html { element, .. } if element == "link");
+            if is_link_element && link.rel.contains(Rel::CANONICAL) && canonical.is_none() {
+                canonical = Some(url.clone());
+            }
+            if let Some(lang) = link.hreflang.filter(|_| is_link_element && link.rel.contains(Rel::ALTERNATE)) {
+                alternates.push(Alternate {
+                    lang: lang.trim().to_ascii_lowercase(),
+                    url: url.clone(),
+                });
+            }
+            Link {
+                url,
+                source: link.source,
+                text: link.text.split_whitespace().collect::<Vec<_>>().join(" "),
+                title: link.title,
+                rel: link.rel,
+                position,
+            }
+        })
+        .collect();
+
+    HtmlDocument {
+        links,
+        robots: sink.robots,
+        canonical,
+        alternates,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'a>(links: &'a [Link], url: &str) -> Option<&'a Link> {
+        let url = Url::parse(url).unwrap();
+        links.iter().find(|link| link.url == url)
+    }
+
+    #[test]
+    fn test_link_elements() {
+        let page = Url::parse("https://www.example.com/a/b.html").unwrap();
+        let content = r#"
+            <a href='single.html'>Single</a>
+            <a href=unquoted.html>Unquoted</a>
+            <a href="/search?a=1&amp;b=2">Entities</a>
+            <link rel="stylesheet" href="style.css">
+            <img src="img.png" srcset="img-1x.png 1x, /img-2x.png 2x">
+            <iframe src="frame.html"></iframe>
+            <area href="area.html">
+            <form action="/submit"></form>
+            <meta http-equiv="Refresh" content="5; URL='/refresh.html'">
+            <script src="app.js"></script>
+            <script>var link = "<a href='in-script.html'>";</script>
+            <style>a { content: "<a href='in-style.html'>" }</style>
+        "#;
+        let links = get_links(content, &page);
+
+        let expected = [
+            ("https://www.example.com/a/single.html", "a", "href"),
+            ("https://www.example.com/a/unquoted.html", "a", "href"),
+            ("https://www.example.com/search?a=1&b=2", "a", "href"),
+            ("https://www.example.com/a/style.css", "link", "href"),
+            ("https://www.example.com/a/img.png", "img", "src"),
+            ("https://www.example.com/a/img-1x.png", "img", "srcset"),
+            ("https://www.example.com/img-2x.png", "img", "srcset"),
+            ("https://www.example.com/a/frame.html", "iframe", "src"),
+            ("https://www.example.com/a/area.html", "area", "href"),
+            ("https://www.example.com/submit", "form", "action"),
+            ("https://www.example.com/refresh.html", "meta", "content"),
+            ("https://www.example.com/a/app.js", "script", "src"),
+        ];
+        for (url, element, attribute) in expected {
+            let link = find(&links, url).unwrap_or_else(|| panic!("{} not in {:?}", url, links));
+            assert_eq!(
+                link.source,
+                LinkSource::Html {
+                    element: element.to_string(),
+                    attribute: attribute.to_string()
+                }
+            );
+        }
+        assert_eq!(links.len(), expected.len());
+        assert!(find(&links, "https://www.example.com/a/in-script.html").is_none());
+        assert!(find(&links, "https://www.example.com/a/in-style.html").is_none());
+    }
+
+    #[test]
+    fn test_styles() {
+        let page = Url::parse("https://www.example.com/a/b.html").unwrap();
+        let content = r#"
+            <style>@import "print.css"; body { background: url(/bg.png) }</style>
+            <div style="background-image: url('hero.jpg')"></div>
+        "#;
+        let links = get_links(content, &page);
+        for url in [
+            "https://www.example.com/a/print.css",
+            "https://www.example.com/bg.png",
+            "https://www.example.com/a/hero.jpg",
+        ] {
+            assert!(find(&links, url).unwrap().is_embedded());
+        }
+        assert_eq!(links.len(), 3);
+    }
+
+    #[test]
+    fn test_scripts() {
+        let page = Url::parse("https://intranet.example.com/a/b.html").unwrap();
+        let content = r#"
+            <button onclick="location.href='/apps/planning.php'">Planning</button>
+            <a href="javascript:window.open('help.html')">Help</a>
+            <script>var next = "/news/2.html";</script>
+            <script type="text/template"><a href="/template.html"></a></script>
+            <script src="app.js"></script>
+        "#;
+        let links = get_links(content, &page);
+        for url in [
+            "https://intranet.example.com/apps/planning.php",
+            "https://intranet.example.com/a/help.html",
+            "https://intranet.example.com/news/2.html",
+        ] {
+            let link = find(&links, url).unwrap_or_else(|| panic!("{} not in {:?}", url, links));
+            assert_eq!(link.source, LinkSource::Script);
+            assert!(!link.is_embedded());
+        }
+        assert!(find(&links, "https://intranet.example.com/template.html").is_none());
+        assert_eq!(links.len(), 4);
+    }
+
+    #[test]
+    fn test_base() {
+        let page = Url::parse("https://www.example.com/a/b.html").unwrap();
+        let content = r#"<a href="before.html"></a><base href="/docs/"><a href="after.html"></a>"#;
+        let links = get_links(content, &page);
+        assert!(find(&links, "https://www.example.com/docs/before.html").is_some());
+        assert!(find(&links, "https://www.example.com/docs/after.html").is_some());
+    }
+
+    #[test]
+    fn test_embedded() {
+        let page = Url::parse("https://www.example.com/").unwrap();
+        let links = get_links(r#"<a href="a.html"></a><img src="b.png">"#, &page);
+        assert!(!find(&links, "https://www.example.com/a.html").unwrap().is_embedded());
+        assert!(find(&links, "https://www.example.com/b.png").unwrap().is_embedded());
+
+        let links = get_links(
+            r#"<link rel="stylesheet" href="a.css"><link rel="alternate" hreflang="en" href="/en/">"#,
+            &page,
+        );
+        assert!(find(&links, "https://www.example.com/a.css").unwrap().is_embedded());
+        assert!(!find(&links, "https://www.example.com/en/").unwrap().is_embedded());
+    }
+
+    #[test]
+    fn test_link_metadata() {
+        let page = Url::parse("https://moodle.example.com/course/view.php?id=1").unwrap();
+        let content = r#"
+            <p>Read the
+                <a href="/pluginfile.php/1/handout.pdf" title="Handout" rel="NoFollow  noopener">
+                    course   <b>handout</b>
+                </a>
+            </p>
+            <a href="/a"><img src="/logo.png" alt="Home"></a>
+            <a href="/b"></a>
+        "#;
+        let links = get_links(content, &page);
+
+        let handout = find(&links, "https://moodle.example.com/pluginfile.php/1/handout.pdf").unwrap();
+        assert_eq!(handout.text, "course handout");
+        assert_eq!(handout.title.as_deref(), Some("Handout"));
+        assert!(handout.rel.contains(Rel::NOFOLLOW | Rel::NOOPENER));
+        assert!(!handout.rel.contains(Rel::EXTERNAL));
+        assert_eq!(handout.position, 0);
+
+        let home = find(&links, "https://moodle.example.com/a").unwrap();
+        assert_eq!(home.text, "Home");
+        assert_eq!(home.position, 1);
+        let logo = find(&links, "https://moodle.example.com/logo.png").unwrap();
+        assert_eq!(logo.text, "Home");
+        assert_eq!(logo.position, 2);
+
+        let empty = find(&links, "https://moodle.example.com/b").unwrap();
+        assert_eq!(empty.text, "");
+        assert!(empty.rel.is_empty());
+        assert_eq!(empty.position, 3);
+
+        // The text of an unclosed link stops at the limit
+        let content = format!("<a href=\"/c\">{}", "é long text ".repeat(1000));
+        let links = get_links(&content, &page);
+        assert!(links[0].text.len() <= MAX_LINK_TEXT && links[0].text.starts_with("é long text"));
+    }
+
+    #[test]
+    fn test_canonical() {
+        let page = Url::parse("https://www.example.com/fr/page?sort=asc").unwrap();
+        let document = parse(
+            r#"
+            <link rel="canonical" href="/fr/page">
+            <link rel="canonical" href="/other">
+            <link rel="alternate" hreflang="EN" href="https://www.example.com/en/page">
+            <link rel="alternate" hreflang="x-default" href="/en/page">
+            <link rel="alternate" type="application/rss+xml" href="/feed">
+            <a rel="canonical" hreflang="de" href="/de/page">Deutsch</a>
+            "#,
+            &page,
+        );
+        assert_eq!(document.canonical, Url::parse("https://www.example.com/fr/page").ok());
+        let en = Url::parse("https://www.example.com/en/page").unwrap();
+        assert_eq!(
+            document.alternates,
+            vec![
+                Alternate {
+                    lang: String::from("en"),
+                    url: en.clone(),
+                },
+                Alternate {
+                    lang: String::from("x-default"),
+                    url: en,
+                },
+            ]
+        );
+
+        let document = parse(r#"<a href="/a">A</a>"#, &page);
+        assert!(document.canonical.is_none() && document.alternates.is_empty());
+    }
+
+    #[test]
+    fn test_robots() {
+        let page = Url::parse("https://www.example.com/").unwrap();
+        let document = parse(
+            r#"<meta name="robots" content="noindex"><meta name="open-finder" content="nofollow">"#,
+            &page,
+        );
+        assert!(document.robots.noindex && document.robots.nofollow);
+        assert!(!document.robots.noarchive);
+
+        let document = parse(r#"<meta name="description" content="noindex">"#, &page);
+        assert_eq!(document.robots, Directives::default());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::link::{Link, LinkSource, Url};
+
+/// Extensions of the pages that can be linked with a relative path
+const PAGE_EXTENSIONS: [&str; 9] = ["html", "htm", "php", "asp", "aspx", "jsp", "pdf", "xml", "txt"];
+
+/// Get the string literals of a script, the comments are skipped and the template
+/// literals with substitutions are ignored
+fn get_strings(content: &str) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            '"' | '\'' | '`' => {
+                let quote = c;
+                let mut value = String::new();
+                let mut is_template = false;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            if let Some(escaped) = chars.next() {
+                                value.push(escaped);
+                            }
+                        }
+                        '\n' if quote != '`' => break,
+                        '$' if quote == '`' && chars.peek() == Some(&'{') => {
+                            is_template = true;
+                            value.push(c);
+                        }
+                        c if c == quote => break,
+                        c => value.push(c),
+                    }
+                }
+                if !is_template {
+                    strings.push(value);
+                }
+            }
+            _ => (),
+        }
+    }
+    strings
+}
+
+/// Whether a string literal looks like an url: an absolute url, a path of the
+/// site or a relative path to a page
+fn is_url_like(value: &str) -> bool {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || "<>{}[]*|^\\\"".contains(c)) {
+        return false;
+    }
+    let lower = value.to_ascii_lowercase();
+    if lower.starts_with("http://") || lower.starts_with("https://") {
+        return true;
+    }
+    if let Some(path) = value.strip_prefix('/') {
+        // `//host/path` or `/path`, but not `/` or a regex like `/\d+/`
+        return path.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '/' || c == '_' || c == '~');
+    }
+    if value.starts_with("./") || value.starts_with("../") {
+        return true;
+    }
+    // `page.php?id=1` but not `text/html` or `a.b`
+    let path = value.split(['?', '#']).next().unwrap_or_default();
+    path.rsplit_once('.')
+        .is_some_and(|(name, extension)| !name.is_empty() && PAGE_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+}
+
+/// Find the string literals of a script that look like urls
+pub fn get_urls(content: &str) -> Vec<String> {
+    get_strings(content)
+        .into_iter()
+        .filter(|value| is_url_like(value))
+        .collect()
+}
+
+/// Parse the links of a script, resolved against the url of the page
+pub fn get_links(content: &str, url: &Url) -> Vec<Link> {
+    get_urls(content)
+        .iter()
+        .filter_map(|value| url.join(value).ok())
+        .enumerate()
+        .map(|(position, target)| Link::new(target, LinkSource::Script, position))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_js_urls() {
+        let content = r#"
+            // location.href = '/commented.html';
+            /* window.open("/commented-too.html") */
+            document.getElementById("menu").onclick = function () {
+                location.href = '/intranet/menu.php?id=2';
+            };
+            window.open("https://www.example.com/popup", "_blank", "width=200");
+            var page = 'news/list.html', type = "text/html", re = /\d+/;
+            fetch(`/api/items`);
+            fetch(`/api/items/${id}`);
+            const cdn = "//cdn.example.com/lib.js";
+            const relative = "../up.htm#top";
+            const escaped = "/it\'s.html";
+            const notUrls = ["a.b", "/", "hello world.html", "{{ url }}", "image/png"];
+        "#;
+        assert_eq!(
+            get_urls(content),
+            vec![
+                "/intranet/menu.php?id=2",
+                "https://www.example.com/popup",
+                "news/list.html",
+                "/api/items",
+                "//cdn.example.com/lib.js",
+                "../up.htm#top",
+                "/it's.html",
+            ]
+        );
+
+        let page = Url::parse("https://www.example.com/a/b.html").unwrap();
+        let links: Vec<String> = get_links(content, &page)
+            .iter()
+            .map(|link| link.url.to_string())
+            .collect();
+        assert_eq!(links[0], "https://www.example.com/intranet/menu.php?id=2");
+        assert_eq!(links[2], "https://www.example.com/a/news/list.html");
+        assert_eq!(links[4], "https://cdn.example.com/lib.js");
+        assert_eq!(links[5], "https://www.example.com/up.htm#top");
+    }
+}
@@ -1,16 +1,35 @@
+pub mod canonical;
+pub mod charset;
+pub mod client;
 pub mod collection;
+pub mod config;
 pub mod content;
+pub mod css;
+pub mod download;
+pub mod dry_run;
+pub mod extraction;
+pub mod feed;
+pub mod html;
+pub mod js;
 pub mod link;
 pub mod manager;
+pub mod pdf;
+pub mod pipeline;
 pub mod prelude;
 pub mod protocols;
+pub mod robots;
+pub mod scope;
+pub mod sitemap;
+#[cfg(test)]
+mod test_server;
+pub mod trap;
 
-use std::fs::File;
+use std::{fs::File, io::Read};
 
 use console::{style, Term};
 use link::Url;
 
-use crate::collection::UrlCollection;
+use crate::{collection::UrlCollection, config::Config};
 
 const NAME_ASCII_ART: &str = r#"
  ___  ____  _____ _   _          _____ ___ _   _ ____  _____ ____
@@ -21,6 +40,49 @@ const NAME_ASCII_ART: &str = r#"
 "#;
 #[tokio::main]
 async fn main() {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            println!("{}", style(err).red());
+            return;
+        }
+    };
+    let mut graph = match UrlCollection::with_config(&config) {
+        Ok(graph) => graph,
+        Err(err) => {
+            println!("{}", style(err).red());
+            return;
+        }
+    };
+
+    // `open-finder --dry-run [file]` explains which urls of the file (or of
+    // stdin) would be fetched, without any request
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--dry-run") {
+        let content = match args.get(2) {
+            Some(path) => std::fs::read_to_string(path),
+            None => {
+                let mut content = String::new();
+                std::io::stdin().read_to_string(&mut content).map(|_| content)
+            }
+        };
+        match content {
+            Ok(content) => dry_run::run(&mut graph, &content),
+            Err(err) => println!("{}", style(err).red()),
+        }
+        return;
+    }
+
+    // `open-finder --migrate-keys` moves the documents indexed with the MD5 of
+    // their url to their fingerprint, once after the upgrade
+    if args.get(1).map(String::as_str) == Some("--migrate-keys") {
+        match content::migrate_keys(&content::index_client()).await {
+            Ok(moved) => println!("{} documents moved to their fingerprint", moved),
+            Err(err) => println!("{}", style(format!("Failed to migrate the index: {}", err)).red()),
+        }
+        return;
+    }
+
     let term = Term::stdout();
     term.clear_screen().unwrap();
     println!("{}", style(NAME_ASCII_ART).green());
@@ -36,7 +98,6 @@ async fn main() {
     );
     let urls = vec![Url::parse(String::from("https://cas.insa-rouen.fr/cas/login?service=https%3A%2F%2Fmoodle.insa-rouen.fr%2Flogin%2Findex.php%3FauthCAS%3DCAS")).unwrap()];
 
-    let mut graph = UrlCollection::new();
     let err = if File::open("fetcheds.csv").is_ok() && File::open("to_fetch.csv").is_ok() {
         graph.load_graph().await;
         // Remove files
@@ -45,6 +106,8 @@ async fn main() {
         // Remove files
         let _ = std::fs::remove_file("fetcheds.csv");
         let _ = std::fs::remove_file("to_fetch.csv");
+        let _ = std::fs::remove_file("recrawl.csv");
+        let _ = std::fs::remove_file("anchors.csv");
         graph.fetch_from(urls).await
     };
 
@@ -0,0 +1,159 @@
+use std::panic;
+
+use lopdf::{Dictionary, Document, Object};
+
+use crate::link::{Link, LinkSource, Url};
+
+/// Get the target of the action of a link annotation: the `URI` of an url action
+/// or the file of a remote go-to or launch action
+fn get_action_target(document: &Document, annotation: &Dictionary) -> Option<String> {
+    let (_, action) = document.dereference(annotation.get(b"A").ok()?).ok()?;
+    let action = action.as_dict().ok()?;
+    let target = match action.get(b"S").ok()?.as_name().ok()? {
+        b"URI" => action.get(b"URI").ok()?,
+        b"GoToR" | b"Launch" => {
+            // The file specification is a string or a dictionary with a `F`
+            let (_, file) = document.dereference(action.get(b"F").ok()?).ok()?;
+            match file {
+                Object::Dictionary(file) => file.get(b"UF").or_else(|_| file.get(b"F")).ok()?,
+                file => file,
+            }
+        }
+        _ => return None,
+    };
+    let (_, target) = document.dereference(target).ok()?;
+    let target = String::from_utf8_lossy(target.as_str().ok()?).trim().to_string();
+    (!target.is_empty()).then_some(target)
+}
+
+/// Get the urls of the link annotations of a pdf, in the order of the pages
+pub fn get_annotation_urls(bytes: &[u8]) -> Vec<String> {
+    // lopdf can panic on malformed documents
+    let document = match panic::catch_unwind(|| Document::load_mem(bytes)) {
+        Ok(Ok(document)) => document,
+        _ => return Vec::new(),
+    };
+    let mut urls = Vec::new();
+    for page in document.get_pages().into_values() {
+        let Ok(page) = document.get_dictionary(page) else {
+            continue;
+        };
+        let annotations = page
+            .get(b"Annots")
+            .ok()
+            .and_then(|annotations| document.dereference(annotations).ok())
+            .and_then(|(_, annotations)| annotations.as_array().ok());
+        for annotation in annotations.into_iter().flatten() {
+            let Some(annotation) = document
+                .dereference(annotation)
+                .ok()
+                .and_then(|(_, annotation)| annotation.as_dict().ok())
+            else {
+                continue;
+            };
+            let is_link = annotation
+                .get(b"Subtype")
+                .and_then(Object::as_name)
+                .is_ok_and(|subtype| subtype == b"Link");
+            if let Some(target) = get_action_target(&document, annotation).filter(|_| is_link) {
+                urls.push(target);
+            }
+        }
+    }
+    urls
+}
+
+/// Parse the links of a pdf: the link annotations, then the urls written in the
+/// text, resolved against the url of the pdf
+pub fn get_links(bytes: &[u8], text: &str, url: &Url) -> Vec<Link> {
+    let annotations = get_annotation_urls(bytes)
+        .into_iter()
+        .filter_map(|value| url.join(&value).ok())
+        .map(|target| (target, LinkSource::Pdf));
+    let mut text_urls: Vec<Url> = crate::link::get_links(text, url.clone()).into_iter().collect();
+    text_urls.sort();
+    annotations
+        .chain(text_urls.into_iter().map(|target| (target, LinkSource::Text)))
+        .enumerate()
+        .map(|(position, (target, source))| Link::new(target, source, position))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::{dictionary, Stream};
+
+    use super::*;
+
+    /// A pdf of a page with a link annotation for each action
+    fn pdf_with_links(actions: Vec<Dictionary>) -> Vec<u8> {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let content_id = document.add_object(Stream::new(dictionary! {}, Vec::new()));
+        let annotations: Vec<Object> = actions
+            .into_iter()
+            .map(|action| {
+                document
+                    .add_object(dictionary! {
+                        "Type" => "Annot",
+                        "Subtype" => "Link",
+                        "Rect" => vec![0.into(), 0.into(), 10.into(), 10.into()],
+                        "A" => action,
+                    })
+                    .into()
+            })
+            .collect();
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+            "Annots" => annotations,
+        });
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog_id);
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_pdf_links() {
+        let bytes = pdf_with_links(vec![
+            dictionary! { "S" => "URI", "URI" => Object::string_literal("https://www.example.com/next.html") },
+            dictionary! { "S" => "GoToR", "F" => Object::string_literal("annexe.pdf") },
+            dictionary! { "S" => "GoTo", "D" => vec![] },
+        ]);
+        assert_eq!(
+            get_annotation_urls(&bytes),
+            vec!["https://www.example.com/next.html", "annexe.pdf"]
+        );
+
+        let pdf = Url::parse("https://moodle.example.com/pluginfile.php/1/cours.pdf").unwrap();
+        let links = get_links(&bytes, "See https://www.example.com/report.pdf for details", &pdf);
+        let links: Vec<(String, LinkSource)> = links
+            .into_iter()
+            .map(|link| (link.url.to_string(), link.source))
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                (String::from("https://www.example.com/next.html"), LinkSource::Pdf),
+                (String::from("https://moodle.example.com/pluginfile.php/1/annexe.pdf"), LinkSource::Pdf),
+                (String::from("https://www.example.com/report.pdf"), LinkSource::Text),
+            ]
+        );
+
+        assert!(get_annotation_urls(b"%PDF-1.5 not a pdf").is_empty());
+    }
+}
@@ -0,0 +1,427 @@
+use std::{future::Future, panic::AssertUnwindSafe, sync::Arc, time::Duration};
+
+use futures::FutureExt;
+use serde::Deserialize;
+use tokio::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Mutex, OwnedSemaphorePermit, Semaphore,
+    },
+    task::JoinHandle,
+};
+
+use crate::{
+    collection::{FetchOptions, Page, PageError},
+    content::{self, Content, ContentType, IndexUpdate},
+    extraction::{ExtractionPool, ExtractionStats},
+    feed::FeedEntry,
+    html::Alternate,
+    link::Url,
+    protocols::UriScheme,
+    robots::{self, RobotsTxt},
+    sitemap::{self, SitemapEntry},
+};
+
+/// Concurrency of the stages of a crawl
+///
+/// ```toml
+/// [pipeline]
+/// fetchers = 20
+/// extractors = 4
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    /// Number of pages downloaded at the same time
+    pub fetchers: usize,
+    /// Number of pages parsed at the same time for their links
+    pub parsers: usize,
+    /// Number of contents whose text is extracted at the same time, on the
+    /// blocking threads
+    pub extractors: usize,
+    /// Capacity of the channels between the stages, a full channel slows down the
+    /// stage that feeds it
+    pub channel_capacity: usize,
+    /// Maximum number of documents sent to the index at once
+    pub index_batch_size: usize,
+    /// Maximum time a document waits for its batch, in seconds
+    pub index_interval: f64,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            fetchers: 20,
+            parsers: 4,
+            extractors: 4,
+            channel_capacity: 64,
+            index_batch_size: 100,
+            index_interval: 1.0,
+        }
+    }
+}
+
+/// A page to fetch, with what the collection knows about it
+pub struct FetchJob {
+    pub url: Url,
+    /// Texts of the links pointing to the page
+    pub anchors: Vec<String>,
+    pub feed_entry: Option<FeedEntry>,
+    /// The canonical of the page is not indexable, the page is indexed in its place
+    pub keep_variant: bool,
+}
+
+impl FetchJob {
+    pub fn new(url: Url) -> Self {
+        FetchJob {
+            url,
+            anchors: Vec::new(),
+            feed_entry: None,
+            keep_variant: false,
+        }
+    }
+}
+
+/// A fetched page, or the error of its url
+pub type Fetched = Result<Page, (Url, PageError)>;
+
+/// What is read about a host before its pages are fetched
+pub struct Discovery {
+    /// The root of the host (`Url::get_root`)
+    pub root: String,
+    pub robots_txt: RobotsTxt,
+    /// Time the robots.txt can be cached
+    pub robots_ttl: Duration,
+    /// The urls of the sitemaps of the host, `None` when they were not asked
+    pub sitemap_entries: Option<Vec<SitemapEntry>>,
+}
+
+/// What a document is published with besides its content: its anchors, its
+/// language versions and its feed entry
+type DocumentInfo = (Vec<String>, Vec<Alternate>, Option<FeedEntry>);
+
+/// A content whose text is extracted to index it or to archive it
+struct Extraction {
+    url: Url,
+    content: Arc<Content>,
+    /// Set when the content is indexed
+    document: Option<DocumentInfo>,
+    archive: bool,
+}
+
+/// The stages of a crawl joined by bounded channels: the fetchers download the
+/// pages, the parsers read their links for the scheduler, the extractors get
+/// the text of their contents for the indexer, which publishes the documents
+/// by batches, and for the archiver. The robots.txt and the sitemaps of the hosts
+/// are fetched in their own tasks.
+pub struct Pipeline {
+    options: FetchOptions,
+    fetchers: Arc<Semaphore>,
+    downloads: Sender<Fetched>,
+    /// The parsed pages, for the scheduler
+    pub results: Receiver<Fetched>,
+    index: Sender<IndexUpdate>,
+    discoveries: Sender<Discovery>,
+    /// The robots.txt and the sitemaps of the hosts, for the scheduler
+    pub discovered: Receiver<Discovery>,
+    extraction: ExtractionPool,
+    stages: Vec<JoinHandle<()>>,
+}
+
+/// Run `workers` tasks taking the items of the channel in turn
+fn spawn_workers<T, F, Fut>(workers: usize, items: Receiver<T>, work: F) -> Vec<JoinHandle<()>>
+where
+    T: Send + 'static,
+    F: Fn(T) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let items = Arc::new(Mutex::new(items));
+    let work = Arc::new(work);
+    (0..workers.max(1))
+        .map(|_| {
+            let (items, work) = (Arc::clone(&items), Arc::clone(&work));
+            tokio::spawn(async move {
+                loop {
+                    // The channel is unlocked before the work
+                    let item = items.lock().await.recv().await;
+                    let Some(item) = item else {
+                        break;
+                    };
+                    work(item).await;
+                }
+            })
+        })
+        .collect()
+}
+
+/// Parse a downloaded page, send its content to the extractors and the page to
+/// the scheduler
+async fn parse(
+    fetched: Fetched,
+    pool: ExtractionPool,
+    results: Sender<Fetched>,
+    extractions: Sender<Extraction>,
+) {
+    let mut fetched = match fetched {
+        Ok(page) => {
+            let url = page.get_url().clone();
+            // The links of a pdf are read in its text, it is extracted by the pool first
+            if let Some(content) = page.get_content().filter(|content| content.get_kind() == &ContentType::Pdf) {
+                pool.extract(Arc::clone(content)).await;
+            }
+            let parsed = tokio::task::spawn_blocking(move || {
+                let mut page = page;
+                page.parse();
+                page
+            });
+            parsed.await.map_err(|_| (url, PageError::Panicked))
+        }
+        Err(err) => Err(err),
+    };
+    let extraction = fetched.as_mut().ok().and_then(|page| {
+        let document = page.is_indexable().then(|| {
+            (
+                page.get_anchors().to_vec(),
+                page.get_alternates().to_vec(),
+                page.get_feed_entry().cloned(),
+            )
+        });
+        let archive = page.is_archivable();
+        let content = page.take_content()?;
+        (document.is_some() || archive).then(|| Extraction {
+            url: page.get_url().clone(),
+            content,
+            document,
+            archive,
+        })
+    });
+    let _ = results.send(fetched).await;
+    if let Some(extraction) = extraction {
+        let _ = extractions.send(extraction).await;
+    }
+}
+
+/// Extract the text of a content, then send its document to the indexer and the
+/// content to the archiver
+async fn extract(
+    extraction: Extraction,
+    pool: ExtractionPool,
+    index: Sender<IndexUpdate>,
+    archives: Sender<(Url, Arc<Content>)>,
+) {
+    let Extraction {
+        url,
+        content,
+        document,
+        archive,
+    } = extraction;
+    pool.extract(Arc::clone(&content)).await;
+    if let Some((anchors, alternates, feed_entry)) = document {
+        let document = content.to_document(url.clone(), &anchors, &alternates, feed_entry.as_ref());
+        let _ = index.send(IndexUpdate::Document(Box::new(document))).await;
+    }
+    if archive {
+        let _ = archives.send((url, content)).await;
+    }
+}
+
+/// Publish the updates by batches, a batch is sent when it is full or `interval`
+/// after the previous one
+async fn index(mut updates: Receiver<IndexUpdate>, batch_size: usize, interval: Duration) {
+    let client = content::index_client();
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        let update = tokio::select! {
+            update = updates.recv() => Some(update),
+            _ = ticker.tick() => None,
+        };
+        let is_closed = matches!(update, Some(None));
+        if let Some(Some(update)) = update {
+            batch.push(update.to_value());
+            if batch.len() < batch_size {
+                continue;
+            }
+        }
+        if !batch.is_empty() {
+            content::update_documents(&client, &batch).await;
+            batch.clear();
+            ticker.reset();
+        }
+        if is_closed {
+            break;
+        }
+    }
+}
+
+/// Archive the contents
+async fn archive(mut archives: Receiver<(Url, Arc<Content>)>) {
+    while let Some((url, content)) = archives.recv().await {
+        content.save(url).await;
+    }
+}
+
+impl Pipeline {
+    /// Start the stages, the pages are fetched with the options
+    pub fn start(config: &PipelineConfig, options: FetchOptions) -> Self {
+        let capacity = config.channel_capacity.max(1);
+        let (downloads, downloads_rx) = mpsc::channel(capacity);
+        let (results_tx, results) = mpsc::channel(capacity);
+        let (extractions, extractions_rx) = mpsc::channel(capacity);
+        let (index_tx, index_rx) = mpsc::channel(capacity.max(config.index_batch_size));
+        let (archives, archives_rx) = mpsc::channel(capacity);
+        let (discoveries, discovered) = mpsc::channel(capacity);
+
+        let extraction = ExtractionPool::new(config.extractors);
+
+        let pool = extraction.clone();
+        let mut stages = spawn_workers(config.parsers, downloads_rx, move |fetched| {
+            parse(fetched, pool.clone(), results_tx.clone(), extractions.clone())
+        });
+        let (pool, index_sender) = (extraction.clone(), index_tx.clone());
+        stages.extend(spawn_workers(config.extractors, extractions_rx, move |extraction| {
+            extract(extraction, pool.clone(), index_sender.clone(), archives.clone())
+        }));
+        let interval = Duration::from_secs_f64(config.index_interval.max(0.001));
+        stages.push(tokio::spawn(index(index_rx, config.index_batch_size.max(1), interval)));
+        stages.push(tokio::spawn(archive(archives_rx)));
+
+        Pipeline {
+            options,
+            fetchers: Arc::new(Semaphore::new(config.fetchers.max(1))),
+            downloads,
+            results,
+            index: index_tx,
+            discoveries,
+            discovered,
+            extraction,
+            stages,
+        }
+    }
+
+    /// Get the semaphore of the fetchers, a permit is needed to fetch a page
+    pub fn fetchers(&self) -> Arc<Semaphore> {
+        Arc::clone(&self.fetchers)
+    }
+
+    /// Download a page in its own task with the permit of a fetcher, the parsed
+    /// page comes back in `results`. The permit is kept until the parsers take the
+    /// page, so that slow parsers or extractors slow down the downloads instead of
+    /// piling up bodies.
+    pub fn fetch(&self, permit: OwnedSemaphorePermit, job: FetchJob) {
+        let options = self.options.clone();
+        let downloads = self.downloads.clone();
+        tokio::spawn(async move {
+            let url = job.url.clone();
+            let download = Page::download(job, options);
+            // A page always comes back, so that the scheduler knows when the crawl ends
+            let fetched = match AssertUnwindSafe(download).catch_unwind().await {
+                Ok(page) => page.map_err(|err| (url, err)),
+                Err(_) => Err((url, PageError::Panicked)),
+            };
+            let _ = downloads.send(fetched).await;
+            drop(permit);
+        });
+    }
+
+    /// Fetch the robots.txt of the host of the url, and its sitemaps if `sitemaps`,
+    /// in their own task so that the scheduler keeps dispatching the other hosts.
+    /// The result comes back in `discovered`.
+    pub fn discover(&self, url: Url, sitemaps: bool) {
+        let client = self.options.client.clone();
+        let discoveries = self.discoveries.clone();
+        tokio::spawn(async move {
+            // A host always comes back, its pages wait for it
+            let (robots_txt, robots_ttl) = AssertUnwindSafe(robots::fetch(&url, &client))
+                .catch_unwind()
+                .await
+                .unwrap_or_else(|_| (RobotsTxt::disallow_all(), robots::UNREACHABLE_ROBOTS_TXT_TTL));
+            let is_http = matches!(url.get_uri_scheme(), UriScheme::Http | UriScheme::Https);
+            let sitemap_entries = match sitemaps && is_http {
+                true => {
+                    let mut sitemaps = robots_txt.get_sitemaps().to_vec();
+                    if sitemaps.is_empty() {
+                        sitemaps.extend(Url::parse(format!("{}sitemap.xml", url.get_root())));
+                    }
+                    let entries = AssertUnwindSafe(sitemap::fetch(sitemaps, &client)).catch_unwind().await;
+                    Some(entries.unwrap_or_default())
+                }
+                false => None,
+            };
+            let discovery = Discovery {
+                root: url.get_root(),
+                robots_txt,
+                robots_ttl,
+                sitemap_entries,
+            };
+            let _ = discoveries.send(discovery).await;
+        });
+    }
+
+    /// Get the counters of the text extractions
+    pub fn extraction_stats(&self) -> ExtractionStats {
+        self.extraction.stats()
+    }
+
+    /// Send updates of the published documents to the indexer
+    pub async fn update_index(&self, updates: Vec<IndexUpdate>) {
+        for update in updates {
+            let _ = self.index.send(update).await;
+        }
+    }
+
+    /// Wait for the stages to process the pages already fetched, the last batch
+    /// is published
+    pub async fn close(self) {
+        let Pipeline {
+            options,
+            fetchers,
+            downloads,
+            results,
+            index,
+            discoveries,
+            discovered,
+            extraction,
+            stages,
+        } = self;
+        // The stages end when their input channels are closed
+        drop((options, fetchers, downloads, results, index, discoveries, discovered, extraction));
+        for stage in stages {
+            let _ = stage.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_pipeline_config() {
+        let config = Config::parse("[pipeline]\nfetchers = 8\nindex_interval = 0.5").unwrap();
+        assert_eq!(config.pipeline.fetchers, 8);
+        assert_eq!(config.pipeline.extractors, 4);
+        assert_eq!(config.pipeline.index_interval, 0.5);
+        assert!(Config::parse("[pipeline]\nworkers = 8").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_error() {
+        // Nothing listens on the port once the listener is dropped
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        drop(listener);
+
+        let mut pipeline = Pipeline::start(&PipelineConfig::default(), FetchOptions::default());
+        let permit = pipeline.fetchers().acquire_owned().await.unwrap();
+        pipeline.fetch(permit, FetchJob::new(url.clone()));
+        // A failed page comes back with its url and frees its fetcher
+        let fetched = pipeline.results.recv().await.unwrap();
+        assert!(matches!(fetched, Err((failed, PageError::ReqwestError(_))) if failed == url));
+        let fetchers = pipeline.fetchers();
+        let all = tokio::time::timeout(Duration::from_secs(1), fetchers.acquire_many(20)).await;
+        assert!(all.is_ok());
+        pipeline.close().await;
+    }
+}
@@ -0,0 +1,483 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    client::HttpClient,
+    link::{normalize_percent_encoding, Url},
+    protocols::UriScheme,
+};
+
+/// Name of the crawler in the robots directives, for example `<meta name="open-finder">`
+pub const ROBOTS_NAME: &str = "open-finder";
+
+/// Directives of the `<meta name="robots">` tags and of the `X-Robots-Tag` headers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Directives {
+    /// The page must not be published
+    pub noindex: bool,
+    /// The links of the page must not be followed
+    pub nofollow: bool,
+    /// The page must not be saved
+    pub noarchive: bool,
+}
+
+impl Directives {
+    /// Parse a comma separated list of directives: `noindex, nofollow`
+    pub fn parse(value: &str) -> Directives {
+        let mut directives = Directives::default();
+        for directive in value.split(',') {
+            match directive.trim().to_ascii_lowercase().as_str() {
+                "noindex" => directives.noindex = true,
+                "nofollow" => directives.nofollow = true,
+                "noarchive" => directives.noarchive = true,
+                "none" => {
+                    directives.noindex = true;
+                    directives.nofollow = true;
+                }
+                _ => (),
+            }
+        }
+        directives
+    }
+
+    /// Parse the value of a `X-Robots-Tag` header, the directives can be prefixed by
+    /// the user agent they apply to: `otherbot: noindex`
+    pub fn parse_header(value: &str) -> Directives {
+        match value.split_once(':') {
+            Some((agent, directives)) if !agent.contains(',') => {
+                if agent.trim().eq_ignore_ascii_case(ROBOTS_NAME) {
+                    Directives::parse(directives)
+                } else {
+                    Directives::default()
+                }
+            }
+            _ => Directives::parse(value),
+        }
+    }
+
+    /// Parse the `<meta>` tag `name` with the directives `content`
+    pub fn parse_meta(name: &str, content: &str) -> Directives {
+        if name.eq_ignore_ascii_case("robots") || name.eq_ignore_ascii_case(ROBOTS_NAME) {
+            Directives::parse(content)
+        } else {
+            Directives::default()
+        }
+    }
+
+    /// Combine the directives, the most restrictive wins
+    pub fn merge(self, other: Directives) -> Directives {
+        Directives {
+            noindex: self.noindex || other.noindex,
+            nofollow: self.nofollow || other.nofollow,
+            noarchive: self.noarchive || other.noarchive,
+        }
+    }
+}
+
+/// Directives to ignore, for internal crawls of our own sites
+///
+/// ```toml
+/// [robots]
+/// ignore_nofollow = true
+/// ```
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RobotsPolicy {
+    pub ignore_noindex: bool,
+    pub ignore_nofollow: bool,
+    pub ignore_noarchive: bool,
+    pub ignore_robots_txt: bool,
+}
+
+impl RobotsPolicy {
+    /// Ignore also the directives whose `IGNORE_NOINDEX`, `IGNORE_NOFOLLOW`,
+    /// `IGNORE_NOARCHIVE` or `IGNORE_ROBOTS_TXT` environment variable is set
+    pub fn with_env(self) -> Self {
+        let is_set = |name: &str| std::env::var(name).is_ok_and(|v| v == "1" || v == "true");
+        RobotsPolicy {
+            ignore_noindex: self.ignore_noindex || is_set("IGNORE_NOINDEX"),
+            ignore_nofollow: self.ignore_nofollow || is_set("IGNORE_NOFOLLOW"),
+            ignore_noarchive: self.ignore_noarchive || is_set("IGNORE_NOARCHIVE"),
+            ignore_robots_txt: self.ignore_robots_txt || is_set("IGNORE_ROBOTS_TXT"),
+        }
+    }
+
+    /// Directives to apply once the ignored ones are removed
+    pub fn apply(&self, directives: Directives) -> Directives {
+        Directives {
+            noindex: directives.noindex && !self.ignore_noindex,
+            nofollow: directives.nofollow && !self.ignore_nofollow,
+            noarchive: directives.noarchive && !self.ignore_noarchive,
+        }
+    }
+}
+
+/// Maximum size of a robots.txt, the rest is ignored (RFC 9309 section 2.5)
+const MAX_ROBOTS_TXT_SIZE: usize = 500 * 1024;
+/// Time before a cached robots.txt is fetched again
+pub const ROBOTS_TXT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Time before a robots.txt unreachable because of the network is fetched again
+pub const UNREACHABLE_ROBOTS_TXT_TTL: Duration = Duration::from_secs(10 * 60);
+/// Number of requests before a robots.txt is unreachable
+const ROBOTS_TXT_ATTEMPTS: u32 = 3;
+/// Wait before the second request of a robots.txt, doubled for each next one
+const ROBOTS_TXT_BACKOFF: Duration = Duration::from_millis(500);
+
+/// An `Allow` or `Disallow` line
+#[derive(Clone, Debug)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+impl Rule {
+    /// Match the path and query of an url, `*` matches any sequence of characters
+    /// and a final `$` matches the end of the url
+    fn matches(&self, path: &str) -> bool {
+        let (pattern, anchored) = match self.pattern.strip_suffix('$') {
+            Some(pattern) => (pattern, true),
+            None => (self.pattern.as_str(), false),
+        };
+        let mut parts = pattern.split('*');
+        let Some(rest) = path.strip_prefix(parts.next().unwrap_or_default()) else {
+            return false;
+        };
+        let parts: Vec<&str> = parts.collect();
+        if parts.is_empty() {
+            return !anchored || rest.is_empty();
+        }
+
+        let mut rest = rest;
+        for (i, part) in parts.iter().enumerate() {
+            if anchored && i == parts.len() - 1 {
+                return rest.ends_with(part);
+            }
+            match rest.find(part) {
+                Some(j) => rest = &rest[j + part.len()..],
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+/// The rules of a robots.txt that apply to the crawler
+#[derive(Clone, Debug, Default)]
+pub struct RobotsTxt {
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+    sitemaps: Vec<Url>,
+}
+
+impl RobotsTxt {
+    /// A robots.txt allowing everything, when there is no robots.txt
+    pub fn allow_all() -> Self {
+        RobotsTxt::default()
+    }
+
+    /// A robots.txt disallowing everything, when the robots.txt is unreachable
+    pub fn disallow_all() -> Self {
+        RobotsTxt {
+            rules: vec![Rule {
+                allow: false,
+                pattern: String::from("/"),
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// Parse a robots.txt and keep the group of the crawler, or the `*` group
+    /// if no group is named after the crawler
+    pub fn parse(content: &str) -> Self {
+        // (user agents, rules, crawl delay)
+        let mut groups: Vec<(Vec<String>, Vec<Rule>, Option<Duration>)> = Vec::new();
+        let mut sitemaps = Vec::new();
+        let mut in_rules = true;
+
+        let mut end = content.len().min(MAX_ROBOTS_TXT_SIZE);
+        while !content.is_char_boundary(end) {
+            end -= 1;
+        }
+        let content = &content[..end];
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    // A user agent after rules starts a new group
+                    if in_rules {
+                        groups.push((Vec::new(), Vec::new(), None));
+                        in_rules = false;
+                    }
+                    if let Some((agents, _, _)) = groups.last_mut() {
+                        agents.push(value.to_ascii_lowercase());
+                    }
+                }
+                key @ ("allow" | "disallow") => {
+                    in_rules = true;
+                    // An empty `Disallow` allows everything
+                    if let (Some((_, rules, _)), false) = (groups.last_mut(), value.is_empty()) {
+                        rules.push(Rule {
+                            allow: key == "allow",
+                            // Encoded like the urls, `;` included
+                            pattern: normalize_percent_encoding(value, |c| {
+                                c.is_ascii_graphic() && c != b'%' && c != b';'
+                            }),
+                        });
+                    }
+                }
+                "crawl-delay" => {
+                    in_rules = true;
+                    if let (Some((_, _, delay)), Ok(seconds)) = (groups.last_mut(), value.parse::<f64>()) {
+                        if seconds.is_finite() && seconds >= 0. {
+                            *delay = Some(Duration::from_secs_f64(seconds));
+                        }
+                    }
+                }
+                "sitemap" => {
+                    if let Ok(url) = Url::parse(value) {
+                        sitemaps.push(url);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let mut robots_txt = RobotsTxt {
+            sitemaps,
+            ..Default::default()
+        };
+        let is_agent = |agents: &Vec<String>, agent: &str| agents.iter().any(|a| a == agent);
+        let Some(agent) = [ROBOTS_NAME, "*"]
+            .into_iter()
+            .find(|agent| groups.iter().any(|(agents, _, _)| is_agent(agents, agent)))
+        else {
+            return robots_txt;
+        };
+        // The groups of the same user agent are merged
+        for (_, rules, delay) in groups.iter().filter(|(agents, _, _)| is_agent(agents, agent)) {
+            robots_txt.rules.extend(rules.iter().cloned());
+            robots_txt.crawl_delay = robots_txt.crawl_delay.or(*delay);
+        }
+        robots_txt
+    }
+
+    /// The url can be fetched, the longest matching rule wins and `Allow` wins
+    /// over `Disallow` for rules of the same length
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        let mut path = url.get_path().to_string();
+        if path == "/robots.txt" {
+            return true;
+        }
+        if let Some(query) = url.get_query() {
+            path.push('?');
+            path.push_str(query);
+        }
+
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(&path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .is_none_or(|rule| rule.allow)
+    }
+
+    /// Delay between two requests asked by the `Crawl-delay` of the group
+    pub fn get_crawl_delay(&self) -> Option<Duration> {
+        self.crawl_delay
+    }
+
+    /// Urls of the `Sitemap` lines
+    pub fn get_sitemaps(&self) -> &[Url] {
+        &self.sitemaps
+    }
+}
+
+/// The robots.txt of each host, by root of the url (`Url::get_root`)
+#[derive(Default)]
+pub struct RobotsCache {
+    /// The robots.txt and when they expire
+    robots_txt: HashMap<String, (Instant, RobotsTxt)>,
+}
+
+impl RobotsCache {
+    pub fn new() -> Self {
+        RobotsCache::default()
+    }
+
+    /// Cache the robots.txt of a root for `ttl`, both given by `fetch`
+    pub fn insert(&mut self, root: String, robots_txt: RobotsTxt, ttl: Duration) {
+        self.robots_txt.insert(root, (Instant::now() + ttl, robots_txt));
+    }
+
+    /// The robots.txt of the host of the url is cached and not expired
+    pub fn is_fresh(&self, url: &Url) -> bool {
+        self.robots_txt
+            .get(&url.get_root())
+            .is_some_and(|(expires_at, _)| Instant::now() < *expires_at)
+    }
+
+    /// Get the robots.txt of the host of the url if it is cached
+    pub fn get_cached(&self, url: &Url) -> Option<&RobotsTxt> {
+        self.robots_txt.get(&url.get_root()).map(|(_, robots_txt)| robots_txt)
+    }
+}
+
+/// Fetch the robots.txt of the host of the url with the time it can be cached.
+/// A missing robots.txt allows everything and an unreachable one disallows
+/// everything (RFC 9309 section 2.3.1).
+///
+/// The request is tried again on a server or a network error. A server still
+/// failing is cached as long as a robots.txt, a network error only for
+/// `UNREACHABLE_ROBOTS_TXT_TTL`, so that a timeout does not exclude a host for a day.
+pub async fn fetch(url: &Url, client: &HttpClient) -> (RobotsTxt, Duration) {
+    if !matches!(url.get_uri_scheme(), UriScheme::Http | UriScheme::Https) {
+        return (RobotsTxt::allow_all(), ROBOTS_TXT_TTL);
+    }
+    let Ok(url) = Url::parse(format!("{}robots.txt", url.get_root())) else {
+        return (RobotsTxt::allow_all(), ROBOTS_TXT_TTL);
+    };
+    let mut server_error = false;
+    for attempt in 0..ROBOTS_TXT_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(ROBOTS_TXT_BACKOFF * 2u32.pow(attempt - 1)).await;
+        }
+        match client.get(&url).send().await {
+            Ok(res) if res.status().is_success() => {
+                if let Ok(content) = res.text().await {
+                    return (RobotsTxt::parse(&content), ROBOTS_TXT_TTL);
+                }
+                server_error = false;
+            }
+            Ok(res) if res.status().is_client_error() => return (RobotsTxt::allow_all(), ROBOTS_TXT_TTL),
+            Ok(res) => server_error = res.status().is_server_error(),
+            Err(_) => server_error = false,
+        }
+    }
+    match server_error {
+        true => (RobotsTxt::disallow_all(), ROBOTS_TXT_TTL),
+        false => (RobotsTxt::disallow_all(), UNREACHABLE_ROBOTS_TXT_TTL),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[test]
+    fn test_directives() {
+        let directives = Directives::parse("NoIndex, nofollow");
+        assert!(directives.noindex && directives.nofollow && !directives.noarchive);
+        assert_eq!(
+            Directives::parse("none"),
+            Directives::parse("noindex,nofollow")
+        );
+        assert_eq!(Directives::parse("all, max-snippet:50"), Directives::default());
+
+        assert!(Directives::parse_header("noarchive").noarchive);
+        assert!(Directives::parse_header("open-finder: noindex").noindex);
+        assert!(!Directives::parse_header("otherbot: noindex").noindex);
+        assert!(Directives::parse_meta("ROBOTS", "noindex").noindex);
+        assert!(!Directives::parse_meta("description", "noindex").noindex);
+
+        let policy = crate::config::Config::parse("[robots]\nignore_nofollow = true").unwrap().robots;
+        let directives = policy.apply(Directives::parse("none"));
+        assert!(directives.noindex && !directives.nofollow);
+    }
+
+    #[test]
+    fn test_robots_txt() {
+        let robots_txt = RobotsTxt::parse(
+            "# comment\n\
+            User-agent: otherbot\n\
+            Disallow: /\n\
+            \n\
+            User-agent: *\n\
+            Disallow: /private/\n\
+            Allow: /private/public\n\
+            Disallow: /*.pdf$\n\
+            Disallow: /search?*q=\n\
+            Disallow:\n\
+            Crawl-delay: 1.5\n\
+            \n\
+            Sitemap: https://www.example.com/sitemap.xml\n",
+        );
+        let is_allowed = |url: &str| robots_txt.is_allowed(&Url::parse(url).unwrap());
+
+        assert!(is_allowed("https://www.example.com/"));
+        assert!(!is_allowed("https://www.example.com/private/"));
+        assert!(!is_allowed("https://www.example.com/private/page"));
+        assert!(is_allowed("https://www.example.com/private/public/page"));
+        assert!(!is_allowed("https://www.example.com/docs/file.pdf"));
+        assert!(is_allowed("https://www.example.com/docs/file.pdf?download=1"));
+        assert!(!is_allowed("https://www.example.com/search?lang=fr&q=insa"));
+        assert!(is_allowed("https://www.example.com/search?lang=fr"));
+        assert!(is_allowed("https://www.example.com/robots.txt"));
+
+        assert_eq!(robots_txt.get_crawl_delay(), Some(Duration::from_millis(1500)));
+        assert_eq!(
+            robots_txt.get_sitemaps(),
+            &[Url::parse("https://www.example.com/sitemap.xml").unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_robots_txt_groups() {
+        // The group of the crawler wins over `*`, groups of the same agent are merged
+        let robots_txt = RobotsTxt::parse(
+            "User-agent: *\nDisallow: /\n\n\
+            User-agent: Open-Finder\nUser-agent: otherbot\nDisallow: /a\n\n\
+            User-agent: open-finder\nDisallow: /b\n",
+        );
+        let is_allowed = |url: &str| robots_txt.is_allowed(&Url::parse(url).unwrap());
+        assert!(is_allowed("https://www.example.com/"));
+        assert!(!is_allowed("https://www.example.com/a"));
+        assert!(!is_allowed("https://www.example.com/b"));
+
+        // Allow wins when the rules have the same length
+        let robots_txt = RobotsTxt::parse("User-agent: *\nDisallow: /page\nAllow: /page\n");
+        assert!(robots_txt.is_allowed(&Url::parse("https://www.example.com/page").unwrap()));
+
+        assert!(!RobotsTxt::disallow_all().is_allowed(&Url::parse("https://a.com/x").unwrap()));
+        assert!(RobotsTxt::allow_all().is_allowed(&Url::parse("https://a.com/x").unwrap()));
+    }
+
+    /// Serve the same response to every request on a local port
+    async fn serve(response: &'static str) -> Url {
+        Url::parse(format!("http://{}/page", test_server::serve(response).await)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fetch_robots_txt() {
+        let client = HttpClient::default();
+        let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 25\r\n\r\nUser-agent: *\nDisallow: /").await;
+        let (robots_txt, ttl) = fetch(&url, &client).await;
+        assert!(!robots_txt.is_allowed(&url));
+        assert_eq!(ttl, ROBOTS_TXT_TTL);
+
+        // A server failing on every attempt disallows the host for a day
+        let url = serve("HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n").await;
+        let (robots_txt, ttl) = fetch(&url, &client).await;
+        assert!(!robots_txt.is_allowed(&url));
+        assert_eq!(ttl, ROBOTS_TXT_TTL);
+
+        // A network error is fetched again soon
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(format!("http://{}/page", listener.local_addr().unwrap())).unwrap();
+        drop(listener);
+        let (robots_txt, ttl) = fetch(&url, &client).await;
+        assert!(!robots_txt.is_allowed(&url));
+        assert_eq!(ttl, UNREACHABLE_ROBOTS_TXT_TTL);
+
+        let mut cache = RobotsCache::new();
+        cache.insert(url.get_root(), robots_txt, Duration::ZERO);
+        assert!(!cache.is_fresh(&url) && cache.get_cached(&url).is_some());
+    }
+}
@@ -0,0 +1,361 @@
+use std::fmt::{Display, Formatter};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{config::ConfigError, link::Url};
+
+/// What a rule does with the urls it matches
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Include,
+    Exclude,
+}
+
+/// A rule of the scope, it matches an url when all its conditions match
+///
+/// ```toml
+/// [[scope.rules]]
+/// action = "exclude"
+/// host = "catalogue.insa-rouen.fr"
+/// path_prefix = "/cgi-bin/koha/opac-search.pl"
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub action: Action,
+    /// Name of the rule in the logs
+    pub name: Option<String>,
+    pub scheme: Option<String>,
+    /// The host, without its subdomains
+    pub host: Option<String>,
+    /// The domain and its subdomains
+    pub domain: Option<String>,
+    pub path_prefix: Option<String>,
+    /// Glob on the whole url, `*` matches any characters, `?` one character and
+    /// `\` escapes the next character
+    pub glob: Option<String>,
+    /// Regex searched in the whole url
+    pub regex: Option<String>,
+    /// Name of a parameter of the query
+    pub query_param: Option<String>,
+}
+
+impl Rule {
+    fn new(action: Action, name: &str) -> Self {
+        Rule {
+            action,
+            name: Some(name.to_string()),
+            scheme: None,
+            host: None,
+            domain: None,
+            path_prefix: None,
+            glob: None,
+            regex: None,
+            query_param: None,
+        }
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(name) = &self.name {
+            return write!(f, "{}", name);
+        }
+        let conditions = [
+            ("scheme", &self.scheme),
+            ("host", &self.host),
+            ("domain", &self.domain),
+            ("path_prefix", &self.path_prefix),
+            ("glob", &self.glob),
+            ("regex", &self.regex),
+            ("query_param", &self.query_param),
+        ];
+        let conditions: Vec<String> = conditions
+            .iter()
+            .filter_map(|(name, value)| Some(format!("{}={}", name, value.as_ref()?)))
+            .collect();
+        if conditions.is_empty() {
+            write!(f, "any")
+        } else {
+            write!(f, "{}", conditions.join(" "))
+        }
+    }
+}
+
+/// The rules of the scope as written in the configuration, the default rules
+/// are only used when there is no `[scope]`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScopeConfig {
+    /// The first matching rule decides
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Action when no rule matches
+    #[serde(default = "default_action")]
+    pub default: Action,
+}
+
+fn default_action() -> Action {
+    Action::Exclude
+}
+
+impl Default for ScopeConfig {
+    /// The scope of the INSA Rouen crawl
+    fn default() -> Self {
+        ScopeConfig {
+            rules: vec![
+                Rule {
+                    regex: Some(String::from("mailto")),
+                    ..Rule::new(Action::Exclude, "mailto")
+                },
+                Rule {
+                    regex: Some(String::from("logout$")),
+                    ..Rule::new(Action::Exclude, "logout")
+                },
+                Rule {
+                    host: Some(String::from("catalogue.insa-rouen.fr")),
+                    path_prefix: Some(String::from("/cgi-bin/koha/opac-search.pl")),
+                    ..Rule::new(Action::Exclude, "koha-opac-search")
+                },
+                Rule {
+                    host: Some(String::from("qualite.insa-rouen.fr")),
+                    ..Rule::new(Action::Exclude, "qualite")
+                },
+                Rule {
+                    domain: Some(String::from("insa-rouen.fr")),
+                    ..Rule::new(Action::Include, "insa-rouen.fr")
+                },
+            ],
+            default: Action::Exclude,
+        }
+    }
+}
+
+/// A rule with its regexes compiled
+struct CompiledRule {
+    rule: Rule,
+    glob: Option<Regex>,
+    regex: Option<Regex>,
+}
+
+impl CompiledRule {
+    fn matches(&self, url: &Url, url_string: &str) -> bool {
+        let rule = &self.rule;
+        let host = url.get_host();
+        rule.scheme
+            .as_ref()
+            .is_none_or(|scheme| scheme.eq_ignore_ascii_case(&url.get_uri_scheme().to_string()))
+            && rule.host.as_ref().is_none_or(|h| h.eq_ignore_ascii_case(host))
+            && rule.domain.as_ref().is_none_or(|domain| {
+                let domain = domain.to_ascii_lowercase();
+                host == domain || host.ends_with(&format!(".{}", domain))
+            })
+            && rule
+                .path_prefix
+                .as_ref()
+                .is_none_or(|prefix| url.get_path().starts_with(prefix.as_str()))
+            && self.glob.as_ref().is_none_or(|glob| glob.is_match(url_string))
+            && self.regex.as_ref().is_none_or(|regex| regex.is_match(url_string))
+            && rule.query_param.as_ref().is_none_or(|param| {
+                url.get_query().is_some_and(|query| {
+                    query
+                        .split('&')
+                        .any(|pair| pair.split('=').next() == Some(param.as_str()))
+                })
+            })
+    }
+}
+
+/// Translate a glob to an anchored regex
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '\\' => regex.push_str(&regex::escape(&chars.next().unwrap_or('\\').to_string())),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// Why an url is in the scope or not
+#[derive(Debug)]
+pub struct Decision<'a> {
+    pub action: Action,
+    /// Index and rule that decided, `None` if no rule matched
+    pub rule: Option<(usize, &'a Rule)>,
+}
+
+impl Decision<'_> {
+    pub fn is_accepted(&self) -> bool {
+        self.action == Action::Include
+    }
+}
+
+impl Display for Decision<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let action = match self.action {
+            Action::Include => "include",
+            Action::Exclude => "exclude",
+        };
+        match self.rule {
+            Some((i, rule)) => write!(f, "{} by rule #{} ({})", action, i + 1, rule),
+            None => write!(f, "{} by default", action),
+        }
+    }
+}
+
+/// Decide which urls are crawled, the first matching rule decides
+pub struct Scope {
+    rules: Vec<CompiledRule>,
+    default: Action,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Scope::new(&ScopeConfig::default()).unwrap()
+    }
+}
+
+impl Scope {
+    pub fn new(config: &ScopeConfig) -> Result<Self, ConfigError> {
+        let compile = |regex: &str| Regex::new(regex).map_err(ConfigError::Regex);
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                Ok(CompiledRule {
+                    glob: rule.glob.as_deref().map(glob_to_regex).as_deref().map(compile).transpose()?,
+                    regex: rule.regex.as_deref().map(compile).transpose()?,
+                    rule: rule.clone(),
+                })
+            })
+            .collect::<Result<_, ConfigError>>()?;
+        Ok(Scope {
+            rules,
+            default: config.default,
+        })
+    }
+
+    pub fn decide(&self, url: &Url) -> Decision<'_> {
+        let url_string = url.to_string();
+        self.rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.matches(url, &url_string))
+            .map(|(i, rule)| Decision {
+                action: rule.rule.action,
+                rule: Some((i, &rule.rule)),
+            })
+            .unwrap_or(Decision {
+                action: self.default,
+                rule: None,
+            })
+    }
+
+    #[inline]
+    pub fn is_accepted(&self, url: &Url) -> bool {
+        self.decide(url).is_accepted()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_default_scope() {
+        let scope = Scope::default();
+        let is_accepted = |url: &str| scope.is_accepted(&Url::parse(url).unwrap());
+        assert!(is_accepted("https://www.insa-rouen.fr/"));
+        assert!(is_accepted("https://moodle.insa-rouen.fr/course/view.php?id=1"));
+        assert!(is_accepted("https://insa-rouen.fr/"));
+        assert!(!is_accepted("https://www.google.com/"));
+        assert!(!is_accepted("https://notinsa-rouen.fr/"));
+        assert!(!is_accepted("https://qualite.insa-rouen.fr/page"));
+        assert!(!is_accepted("https://catalogue.insa-rouen.fr/cgi-bin/koha/opac-search.pl?q=a"));
+        assert!(is_accepted("https://catalogue.insa-rouen.fr/cgi-bin/koha/opac-detail.pl"));
+        assert!(!is_accepted("https://cas.insa-rouen.fr/cas/logout"));
+
+        // The example configuration is the default scope
+        let config = Config::parse(include_str!("../open-finder.example.toml")).unwrap();
+        assert_eq!(config.scope.rules.len(), ScopeConfig::default().rules.len());
+        let example = Scope::new(&config.scope).unwrap();
+        for url in ["https://www.insa-rouen.fr/", "https://qualite.insa-rouen.fr/", "https://a.com/"] {
+            let url = Url::parse(url).unwrap();
+            assert_eq!(example.decide(&url).to_string(), scope.decide(&url).to_string());
+        }
+    }
+
+    #[test]
+    fn test_configured_scope() {
+        let config = Config::parse(
+            r#"
+            [scope]
+            default = "include"
+
+            [[scope.rules]]
+            action = "exclude"
+            scheme = "http"
+
+            [[scope.rules]]
+            action = "exclude"
+            name = "sessions"
+            query_param = "sesskey"
+
+            [[scope.rules]]
+            action = "include"
+            host = "www.example.com"
+            path_prefix = "/public/"
+
+            [[scope.rules]]
+            action = "exclude"
+            glob = "https://www.example.com/*.php"
+
+            [[scope.rules]]
+            action = "exclude"
+            regex = "/[0-9]{4}/[0-9]{2}/"
+
+            [[scope.rules]]
+            action = "exclude"
+            glob = 'https://www.example.com/search\?*'
+            "#,
+        )
+        .unwrap();
+        let scope = Scope::new(&config.scope).unwrap();
+        let decide = |url: &str| {
+            let decision = scope.decide(&Url::parse(url).unwrap());
+            (decision.is_accepted(), decision.rule.map(|(i, _)| i))
+        };
+
+        assert_eq!(decide("http://www.example.com/"), (false, Some(0)));
+        assert_eq!(decide("https://www.example.com/a?x=1&sesskey=2"), (false, Some(1)));
+        assert_eq!(decide("https://www.example.com/a?notsesskey=2"), (true, None));
+        assert_eq!(decide("https://www.example.com/public/a.php"), (true, Some(2)));
+        assert_eq!(decide("https://www.example.com/a/b.php"), (false, Some(3)));
+        assert_eq!(decide("https://www.example.com/news/2024/01/a"), (false, Some(4)));
+        assert_eq!(decide("https://other.example.com/"), (true, None));
+        assert_eq!(decide("https://www.example.com/search?q=1"), (false, Some(5)));
+        assert_eq!(decide("https://www.example.com/searches"), (true, None));
+
+        assert_eq!(
+            scope.decide(&Url::parse("https://www.example.com/a?sesskey=1").unwrap()).to_string(),
+            "exclude by rule #2 (sessions)"
+        );
+        assert_eq!(
+            scope.decide(&Url::parse("http://www.example.com/").unwrap()).to_string(),
+            "exclude by rule #1 (scheme=http)"
+        );
+
+        assert!(Config::parse("[scope]\nunknown = 1").is_err());
+        let config = Config::parse("[[scope.rules]]\naction = \"include\"\nregex = \"(\"").unwrap();
+        assert!(Scope::new(&config.scope).is_err());
+    }
+}
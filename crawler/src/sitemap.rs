@@ -0,0 +1,343 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    io::Read,
+    time::Duration,
+};
+
+use flate2::read::GzDecoder;
+use quick_xml::{events::Event, Reader};
+
+use crate::{client::HttpClient, download, link::Url};
+
+/// Maximum number of sitemaps fetched from the same seeds, indexes included
+const MAX_SITEMAPS: usize = 1_000;
+/// Maximum size of an uncompressed sitemap (sitemaps.org protocol)
+const MAX_SITEMAP_SIZE: u64 = 50 * 1024 * 1024;
+
+/// How often a page is likely to change, the `<changefreq>` of a sitemap
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeFreq {
+    Always,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Never,
+}
+
+impl ChangeFreq {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "always" => Some(ChangeFreq::Always),
+            "hourly" => Some(ChangeFreq::Hourly),
+            "daily" => Some(ChangeFreq::Daily),
+            "weekly" => Some(ChangeFreq::Weekly),
+            "monthly" => Some(ChangeFreq::Monthly),
+            "yearly" => Some(ChangeFreq::Yearly),
+            "never" => Some(ChangeFreq::Never),
+            _ => None,
+        }
+    }
+
+    /// Time after which a page is fetched again, `None` for a page that never
+    /// changes. `always` is fetched hourly to spare the server.
+    pub fn interval(self) -> Option<Duration> {
+        const HOUR: u64 = 60 * 60;
+        let hours = match self {
+            ChangeFreq::Always | ChangeFreq::Hourly => 1,
+            ChangeFreq::Daily => 24,
+            ChangeFreq::Weekly => 7 * 24,
+            ChangeFreq::Monthly => 30 * 24,
+            ChangeFreq::Yearly => 365 * 24,
+            ChangeFreq::Never => return None,
+        };
+        Some(Duration::from_secs(hours * HOUR))
+    }
+}
+
+/// Hints of a sitemap for the re-crawl and the prioritization of an url
+#[derive(Clone, Debug, PartialEq)]
+pub struct SitemapHint {
+    /// W3C datetime of the last modification, as written in the sitemap
+    pub lastmod: Option<String>,
+    pub changefreq: Option<ChangeFreq>,
+    /// Between 0 and 1, 0.5 by default
+    pub priority: f32,
+}
+
+impl SitemapHint {
+    /// The `<lastmod>` in seconds since the Unix epoch
+    pub fn lastmod_timestamp(&self) -> Option<u64> {
+        parse_datetime(self.lastmod.as_deref()?)
+    }
+
+    /// The url is listed with a priority over the default one, it is fetched first
+    pub fn is_prioritized(&self) -> bool {
+        self.priority > SitemapHint::default().priority
+    }
+}
+
+/// Parse a W3C datetime (`2024-09-01`, `2024-09-01T10:30:00+02:00`...) to seconds
+/// since the Unix epoch
+fn parse_datetime(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (date, time) = value.split_once('T').unwrap_or((value, ""));
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next().map_or(Ok(1), str::parse).ok()?;
+    let day: i64 = parts.next().map_or(Ok(1), str::parse).ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Days since the epoch of the proleptic Gregorian calendar
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let mut seconds = 0;
+    if !time.is_empty() {
+        // The time zone is `Z` or `+hh:mm`
+        let (clock, offset) = match time.find(['Z', '+', '-']) {
+            Some(i) => time.split_at(i),
+            None => (time, ""),
+        };
+        let mut clock = clock.split(':');
+        let hours: i64 = clock.next()?.parse().ok()?;
+        let minutes: i64 = clock.next()?.parse().ok()?;
+        let secs = clock.next().map_or(Ok(0.0), str::parse::<f64>).ok()? as i64;
+        seconds = hours * 3600 + minutes * 60 + secs;
+        if let Some((sign, offset)) = offset.split_at_checked(1).filter(|(sign, _)| *sign != "Z") {
+            let (offset_hours, offset_minutes) = offset.split_once(':')?;
+            let offset = offset_hours.parse::<i64>().ok()? * 3600 + offset_minutes.parse::<i64>().ok()? * 60;
+            seconds -= if sign == "-" { -offset } else { offset };
+        }
+    }
+    u64::try_from(days * 86_400 + seconds).ok()
+}
+
+impl Default for SitemapHint {
+    fn default() -> Self {
+        SitemapHint {
+            lastmod: None,
+            changefreq: None,
+            priority: 0.5,
+        }
+    }
+}
+
+/// An url of a sitemap
+#[derive(Clone, Debug, PartialEq)]
+pub struct SitemapEntry {
+    pub url: Url,
+    pub hint: SitemapHint,
+}
+
+/// A sitemap lists pages, a sitemap index lists other sitemaps
+#[derive(Debug, PartialEq)]
+pub enum Sitemap {
+    UrlSet(Vec<SitemapEntry>),
+    Index(Vec<Url>),
+}
+
+/// Uncompress the content if it is gzipped
+fn decompress(bytes: &[u8]) -> Vec<u8> {
+    if !bytes.starts_with(&[0x1f, 0x8b]) {
+        return bytes.to_vec();
+    }
+    let mut content = Vec::new();
+    let _ = GzDecoder::new(bytes)
+        .take(MAX_SITEMAP_SIZE)
+        .read_to_end(&mut content);
+    content
+}
+
+/// Parse a xml sitemap or sitemap index, gzipped or not, or a text sitemap
+/// with an url by line
+pub fn parse(bytes: &[u8]) -> Sitemap {
+    let bytes = decompress(bytes);
+    let content = String::from_utf8_lossy(&bytes);
+    if !content.trim_start().starts_with('<') {
+        return Sitemap::UrlSet(
+            content
+                .lines()
+                .filter_map(|line| Url::parse(line).ok())
+                .map(|url| SitemapEntry {
+                    url,
+                    hint: SitemapHint::default(),
+                })
+                .collect(),
+        );
+    }
+
+    let mut reader = Reader::from_str(&content);
+    reader.config_mut().trim_text(true);
+
+    let mut is_index = false;
+    let mut entries = Vec::new();
+    let mut sitemaps = Vec::new();
+    // The `<url>` or `<sitemap>` we are in: (loc, hint)
+    let mut entry: Option<(Option<Url>, SitemapHint)> = None;
+    let mut field: Vec<u8> = Vec::new();
+    loop {
+        let text = match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                match e.local_name().as_ref() {
+                    b"sitemapindex" => is_index = true,
+                    b"url" | b"sitemap" => entry = Some((None, SitemapHint::default())),
+                    name => field = name.to_vec(),
+                }
+                continue;
+            }
+            Ok(Event::End(e)) => {
+                match (e.local_name().as_ref(), entry.take()) {
+                    (b"url", Some((Some(url), hint))) => entries.push(SitemapEntry { url, hint }),
+                    (b"sitemap", Some((Some(url), _))) => sitemaps.push(url),
+                    (_, current) => entry = current,
+                }
+                field.clear();
+                continue;
+            }
+            Ok(Event::Text(text)) => text.unescape().map(|text| text.into_owned()).unwrap_or_default(),
+            Ok(Event::CData(text)) => String::from_utf8_lossy(&text).into_owned(),
+            Ok(Event::Eof) | Err(_) => break,
+            _ => continue,
+        };
+
+        if let Some((loc, hint)) = entry.as_mut() {
+            match field.as_slice() {
+                b"loc" => *loc = Url::parse(text.trim()).ok(),
+                b"lastmod" => hint.lastmod = Some(text.trim().to_string()),
+                b"changefreq" => hint.changefreq = ChangeFreq::parse(&text),
+                b"priority" => {
+                    if let Ok(priority) = text.trim().parse::<f32>() {
+                        hint.priority = priority.clamp(0., 1.);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    if is_index {
+        Sitemap::Index(sitemaps)
+    } else {
+        Sitemap::UrlSet(entries)
+    }
+}
+
+/// Fetch the sitemaps and the sitemaps of their indexes, and get all the urls they list
+pub async fn fetch(sitemaps: Vec<Url>, client: &HttpClient) -> Vec<SitemapEntry> {
+    let mut to_fetch: VecDeque<Url> = sitemaps.into();
+    let mut fetched = HashSet::new();
+    let mut entries = Vec::new();
+    while let Some(url) = to_fetch.pop_front() {
+        if fetched.len() >= MAX_SITEMAPS || !fetched.insert(url.get_hash()) {
+            continue;
+        }
+        let res = client.get(&url).send().await;
+        // A sitemap over the limit of the protocol is not read, its last url could be cut
+        let bytes = match res {
+            Ok(res) if res.status().is_success() => match download::read_body(res, |_| MAX_SITEMAP_SIZE).await {
+                Ok((bytes, false)) => bytes,
+                _ => continue,
+            },
+            _ => continue,
+        };
+        match parse(&bytes) {
+            Sitemap::UrlSet(urls) => entries.extend(urls),
+            Sitemap::Index(sitemaps) => to_fetch.extend(sitemaps),
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    const URLSET: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+            <url>
+                <loc>https://www.example.com/?a=1&amp;b=2</loc>
+                <lastmod>2024-09-01</lastmod>
+                <changefreq>Weekly</changefreq>
+                <priority>0.8</priority>
+            </url>
+            <url><loc><![CDATA[https://www.example.com/news]]></loc></url>
+            <url><loc>not an url</loc></url>
+        </urlset>"#;
+
+    #[test]
+    fn test_urlset() {
+        let expected = Sitemap::UrlSet(vec![
+            SitemapEntry {
+                url: Url::parse("https://www.example.com/?a=1&b=2").unwrap(),
+                hint: SitemapHint {
+                    lastmod: Some(String::from("2024-09-01")),
+                    changefreq: Some(ChangeFreq::Weekly),
+                    priority: 0.8,
+                },
+            },
+            SitemapEntry {
+                url: Url::parse("https://www.example.com/news").unwrap(),
+                hint: SitemapHint::default(),
+            },
+        ]);
+        assert_eq!(parse(URLSET.as_bytes()), expected);
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(URLSET.as_bytes()).unwrap();
+        assert_eq!(parse(&encoder.finish().unwrap()), expected);
+    }
+
+    #[test]
+    fn test_hints() {
+        let hint = |lastmod: &str| SitemapHint {
+            lastmod: Some(String::from(lastmod)),
+            ..SitemapHint::default()
+        };
+        assert_eq!(hint("1970-01-02").lastmod_timestamp(), Some(86_400));
+        assert_eq!(hint("2024-09-01").lastmod_timestamp(), Some(1_725_148_800));
+        assert_eq!(hint("2024-09-01T10:30:15.5Z").lastmod_timestamp(), Some(1_725_186_615));
+        assert_eq!(hint("2024-09-01T12:30+02:00").lastmod_timestamp(), Some(1_725_186_600));
+        assert_eq!(hint("2024").lastmod_timestamp(), Some(1_704_067_200));
+        assert_eq!(hint("yesterday").lastmod_timestamp(), None);
+
+        assert_eq!(ChangeFreq::Daily.interval(), Some(Duration::from_secs(86_400)));
+        assert_eq!(ChangeFreq::Never.interval(), None);
+        assert!(SitemapHint { priority: 0.8, ..SitemapHint::default() }.is_prioritized());
+        assert!(!SitemapHint::default().is_prioritized());
+    }
+
+    #[test]
+    fn test_index() {
+        let content = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+            <sitemap><loc>https://www.example.com/sitemap1.xml.gz</loc><lastmod>2024-01-01</lastmod></sitemap>
+            <sitemap><loc>https://www.example.com/sitemap2.xml</loc></sitemap>
+        </sitemapindex>"#;
+        assert_eq!(
+            parse(content.as_bytes()),
+            Sitemap::Index(vec![
+                Url::parse("https://www.example.com/sitemap1.xml.gz").unwrap(),
+                Url::parse("https://www.example.com/sitemap2.xml").unwrap(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_text() {
+        let content = "https://www.example.com/a\nhttps://www.example.com/b\n";
+        match parse(content.as_bytes()) {
+            Sitemap::UrlSet(entries) => assert_eq!(entries.len(), 2),
+            Sitemap::Index(_) => panic!("not an index"),
+        }
+    }
+}
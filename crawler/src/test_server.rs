@@ -0,0 +1,26 @@
+//! Local HTTP server answering the requests of the tests
+
+use std::net::SocketAddr;
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+
+/// Serve the same response to every request on a local port
+pub async fn serve(response: &'static str) -> SocketAddr {
+    serve_with(move |_, _| response.to_string()).await
+}
+
+/// Serve the response built from the path of each request and the address of the server
+pub async fn serve_with(respond: impl Fn(&str, SocketAddr) -> String + Send + 'static) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut request = [0; 1024];
+            let read = socket.read(&mut request).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&request[..read]);
+            let path = request.split_whitespace().nth(1).unwrap_or("/");
+            let _ = socket.write_all(respond(path, addr).as_bytes()).await;
+        }
+    });
+    addr
+}
@@ -0,0 +1,235 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter},
+};
+
+use serde::Deserialize;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::link::Url;
+
+/// Limits of the trap heuristics
+///
+/// ```toml
+/// [traps]
+/// max_url_length = 2048
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrapConfig {
+    pub max_url_length: usize,
+    /// Maximum number of times a segment can appear in a path
+    pub max_segment_repeats: usize,
+    /// Maximum number of distinct queries for a path
+    pub max_query_combinations: usize,
+    /// Maximum number of urls of a host matching a generated looking pattern,
+    /// such as `/calendar/*/*`
+    pub max_generated_urls: usize,
+}
+
+impl Default for TrapConfig {
+    fn default() -> Self {
+        TrapConfig {
+            max_url_length: 2048,
+            max_segment_repeats: 2,
+            max_query_combinations: 256,
+            max_generated_urls: 2_000,
+        }
+    }
+}
+
+/// The heuristic that caught an url
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Trap {
+    TooLong,
+    RepeatedSegments,
+    QueryCombinations,
+    GeneratedUrls,
+}
+
+impl Display for Trap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Trap::TooLong => write!(f, "url too long"),
+            Trap::RepeatedSegments => write!(f, "repeated path segments"),
+            Trap::QueryCombinations => write!(f, "too many queries for the path"),
+            Trap::GeneratedUrls => write!(f, "too many generated urls"),
+        }
+    }
+}
+
+/// A pattern whose urls are trapped, the pattern is a glob that can be used
+/// in a scope rule
+#[derive(Debug, PartialEq, Eq)]
+pub struct TrappedPattern {
+    pub trap: Trap,
+    pub pattern: String,
+    /// Number of urls rejected
+    pub rejected: usize,
+}
+
+/// Whether a segment of a path looks generated: numbers, dates or ids
+fn is_generated(segment: &str) -> bool {
+    let has_digit = segment.bytes().any(|c| c.is_ascii_digit());
+    let is_number = segment.bytes().all(|c| c.is_ascii_digit() || b"-_.".contains(&c));
+    let is_id = segment.len() >= 16 && segment.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_');
+    has_digit && (is_number || is_id)
+}
+
+/// The path of the url where the generated segments are replaced by `*`
+fn path_pattern(url: &Url) -> String {
+    url.get_path()
+        .split('/')
+        .map(|segment| if is_generated(segment) { "*" } else { segment })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Find a segment repeated too many times, the pattern stops before its
+/// first repetition
+fn repeated_segments(path: &str, max_repeats: usize) -> Option<String> {
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for segment in segments.iter() {
+        *counts.entry(segment).or_default() += 1;
+    }
+    let repeated = *segments.iter().find(|segment| counts[*segment] > max_repeats)?;
+    let first = segments.iter().position(|segment| *segment == repeated)?;
+    let second = first + 1 + segments[first + 1..].iter().position(|segment| *segment == repeated)?;
+    Some(format!("/{}/*", segments[..second].join("/")))
+}
+
+/// Detect the urls of infinite spaces: calendars, faceted searches, session ids
+/// in paths or repeated segments
+#[derive(Default)]
+pub struct TrapDetector {
+    config: TrapConfig,
+    /// Hashes of the distinct queries by path
+    queries: HashMap<String, HashSet<u64>>,
+    /// Number of urls by generated looking pattern
+    generated: HashMap<String, usize>,
+    /// Number of rejected urls by trap and pattern
+    trapped: HashMap<(Trap, String), usize>,
+}
+
+impl TrapDetector {
+    pub fn new(config: &TrapConfig) -> Self {
+        TrapDetector {
+            config: config.clone(),
+            ..TrapDetector::default()
+        }
+    }
+
+    /// Check a new url, it is counted in the budgets if it is not trapped
+    pub fn check(&mut self, url: &Url) -> Option<Trap> {
+        let (trap, pattern) = self.find_trap(url)?;
+        *self.trapped.entry((trap, pattern)).or_default() += 1;
+        Some(trap)
+    }
+
+    fn find_trap(&mut self, url: &Url) -> Option<(Trap, String)> {
+        let root = url.get_root();
+        let root = root.trim_end_matches('/');
+        let url_string = url.to_string();
+        if url_string.len() > self.config.max_url_length {
+            let first_segment = url.get_path().split('/').nth(1).unwrap_or_default();
+            return Some((Trap::TooLong, format!("{}/{}*", root, first_segment)));
+        }
+        if let Some(pattern) = repeated_segments(url.get_path(), self.config.max_segment_repeats) {
+            return Some((Trap::RepeatedSegments, format!("{}{}", root, pattern)));
+        }
+
+        let pattern = format!("{}{}", root, path_pattern(url));
+        if let Some(query) = url.get_query() {
+            let hash = xxh3_64(query.as_bytes());
+            let queries = self.queries.entry(pattern.clone()).or_default();
+            if queries.len() >= self.config.max_query_combinations && !queries.contains(&hash) {
+                // The `?` of the query is escaped in the glob
+                return Some((Trap::QueryCombinations, format!("{}\\?*", pattern)));
+            }
+            queries.insert(hash);
+        }
+        if pattern.contains('*') {
+            let count = self.generated.entry(pattern.clone()).or_default();
+            if *count >= self.config.max_generated_urls {
+                return Some((Trap::GeneratedUrls, pattern));
+            }
+            *count += 1;
+        }
+        None
+    }
+
+    /// Get the trapped patterns, the most rejected first
+    pub fn get_trapped(&self) -> Vec<TrappedPattern> {
+        let mut trapped: Vec<TrappedPattern> = self
+            .trapped
+            .iter()
+            .map(|((trap, pattern), rejected)| TrappedPattern {
+                trap: *trap,
+                pattern: pattern.clone(),
+                rejected: *rejected,
+            })
+            .collect();
+        trapped.sort_by(|a, b| b.rejected.cmp(&a.rejected).then_with(|| a.pattern.cmp(&b.pattern)));
+        trapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traps() {
+        let mut detector = TrapDetector::new(&TrapConfig {
+            max_url_length: 64,
+            max_segment_repeats: 2,
+            max_query_combinations: 2,
+            max_generated_urls: 3,
+        });
+        let mut check = |url: &str| detector.check(&Url::parse(url).unwrap());
+
+        assert_eq!(check("https://a.fr/a/b/a/b"), None);
+        assert_eq!(check("https://a.fr/a/b/a/b/a/b"), Some(Trap::RepeatedSegments));
+        assert_eq!(check(&format!("https://a.fr/long/{}", "x".repeat(64))), Some(Trap::TooLong));
+
+        assert_eq!(check("https://a.fr/search?q=1"), None);
+        assert_eq!(check("https://a.fr/search?q=2"), None);
+        assert_eq!(check("https://a.fr/search?q=1"), None);
+        assert_eq!(check("https://a.fr/search?q=3"), Some(Trap::QueryCombinations));
+        assert_eq!(check("https://a.fr/other?q=3"), None);
+
+        assert_eq!(check("https://a.fr/calendar/2024-01-01/day"), None);
+        assert_eq!(check("https://a.fr/calendar/2024-01-02/day"), None);
+        assert_eq!(check("https://a.fr/calendar/2024-01-03/day"), None);
+        assert_eq!(check("https://a.fr/calendar/2024-01-04/day"), Some(Trap::GeneratedUrls));
+        assert_eq!(check("https://b.fr/calendar/2024-01-04/day"), None);
+        assert_eq!(check("https://a.fr/s/0123456789abcdef0123/day"), None);
+
+        assert_eq!(
+            detector.get_trapped(),
+            vec![
+                TrappedPattern {
+                    trap: Trap::RepeatedSegments,
+                    pattern: String::from("https://a.fr/a/b/*"),
+                    rejected: 1,
+                },
+                TrappedPattern {
+                    trap: Trap::GeneratedUrls,
+                    pattern: String::from("https://a.fr/calendar/*/day"),
+                    rejected: 1,
+                },
+                TrappedPattern {
+                    trap: Trap::TooLong,
+                    pattern: String::from("https://a.fr/long*"),
+                    rejected: 1,
+                },
+                TrappedPattern {
+                    trap: Trap::QueryCombinations,
+                    pattern: String::from("https://a.fr/search\\?*"),
+                    rejected: 1,
+                },
+            ]
+        );
+    }
+}
@@ -2,13 +2,17 @@ use std::panic;
 
 use html2text::from_read;
 
-pub async fn extract_text(bytes: &str, txt: &mut String)  {
+/// Extract the text of a HTML page. It is CPU bound and blocks the thread, run it
+/// out of the async tasks.
+pub fn extract_text(bytes: &str, txt: &mut String)  {
     
     let bytes = bytes.as_bytes();
     *txt = from_read(bytes, 1000);
 }
 
-pub async fn extract_text_from_pdf(bytes: &[u8], txt: &mut String) {
+/// Extract the text of a PDF. It is CPU bound and blocks the thread, run it out
+/// of the async tasks.
+pub fn extract_text_from_pdf(bytes: &[u8], txt: &mut String) {
     // Catch unwinding panics
     let text = panic::catch_unwind(|| {
         pdf_extract::extract_text_from_mem(bytes)